@@ -1,18 +1,23 @@
 extern crate pyo3;
 
 use pyo3::{prelude::*};
-use crate::request::ProcessedRequest;
-use crate::File;
+use pyo3::exceptions::{PyImportError, PyValueError};
+use pyo3::types::{PyBytes, PyDict, PyString};
+use crate::app::{switching_protocols, websocket_accept, PyStream, PyWebSocket, Watchdog};
+use crate::assets::Assets;
+use crate::config::Config;
+use crate::interpreter::Interpreter;
+use crate::ratelimit::{RateLimit, RateLimitStatus, RateLimiter};
+use crate::request::{cookie_key, BadRequest, BodyLimits, PyRequest, TrustedProxies};
+use crate::response::{is_event_stream, Compression, Conditions, ErrorReport, Response};
+use crate::server::{overloaded, websocket_channel, Job, JobQueue, Reply};
 use crate::Python;
-use crate::Request;
-use pythonize::pythonize;
+use crate::request::Request;
+use cookie::Key;
 use serde::Serialize;
-use std::collections::HashMap;
-use std::io::Read;
-use std::time::Instant;
-use std::sync::mpsc::Receiver;
-use std::net::TcpStream;
-use std::io::Write;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Duration;
 
 #[allow(dead_code)]
 #[derive(Serialize, Debug, Clone)]
@@ -42,27 +47,112 @@ impl RouteNode {
 pub struct App {
     raw_routes: HashMap<String, String>,
     routes_tree: Box<RouteNode>,
+    //the handlers of the routes declared with the `ws` flag
+    websocket_handlers: HashSet<String>,
+    //the handlers of the routes with a `timeout=` flag, None when they have none
+    handler_timeouts: HashMap<String, Option<Duration>>,
+    //the limit of the config and the ones of the routes with a `rate=` flag, shared by the workers
+    #[serde(skip)]
+    rate_limiter: RateLimiter,
     #[serde(skip)]
     handlers: HashMap<(String, String), PyObject>,
     #[serde(skip)] 
-    python_app: Option<PyObject>
+    python_app: Option<PyObject>,
+    #[serde(skip)] 
+    config: Config,
+    //derived from the `secret_key` of the config, once
+    #[serde(skip)]
+    cookie_key: Option<Key>,
+    #[serde(skip)]
+    trusted_proxies: TrustedProxies,
+    #[serde(skip)]
+    assets: Assets,
+    #[serde(skip)]
+    compression: Compression,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl App {
     pub fn new() -> Self {
+        Self::from_config(Config::default())
+    }
+
+    pub fn from_config(config: Config) -> Self {
         Self {
             raw_routes: HashMap::new(),
             routes_tree: Box::new(RouteNode::default()),
+            websocket_handlers: HashSet::new(),
+            handler_timeouts: HashMap::new(),
+            rate_limiter: RateLimiter::from_config(&config),
             handlers: HashMap::new(), 
             python_app: None, 
+            cookie_key: config.secret_key.as_deref().map(cookie_key),
+            //checked when the config was loaded
+            trusted_proxies: TrustedProxies::parse(&config.trusted_proxies).unwrap_or_default(),
+            assets: Assets::from_config(&config),
+            compression: Compression::from_config(&config),
+            config,
         }
     }
 
     pub fn register_routes(&mut self, raw_routes: Vec<(String, String)>) {
         //the dict consists of keys: route paths and values: handler paths
         //create a tree to resolve the paths in linear time
+        //the route can be followed by flags, like `/chat ws` for websocket endpoints
+        let raw_routes: Vec<(String, String)> = raw_routes
+            .into_iter()
+            .map(|(raw_route, handler)| {
+                let mut parts = raw_route.split_whitespace();
+                let route = parts.next().unwrap_or_default().to_string();
+                for flag in parts {
+                    match flag {
+                        "ws" => {
+                            self.websocket_handlers.insert(handler.clone());
+                        }
+                        "timeout=none" => {
+                            self.handler_timeouts.insert(handler.clone(), None);
+                        }
+                        flag if flag.starts_with("timeout=") => match flag["timeout=".len()..].parse::<f64>() {
+                            Ok(timeout) if timeout > 0.0 && timeout.is_finite() => {
+                                self.handler_timeouts.insert(handler.clone(), Some(Duration::from_secs_f64(timeout)));
+                            }
+                            _ => println!("Invalid `{}` for the route {}, it takes seconds or `none`", flag, route),
+                        },
+                        "rate=none" => {
+                            self.rate_limiter.set_route(handler.clone(), None);
+                        }
+                        flag if flag.starts_with("rate=") => match RateLimit::parse(&flag["rate=".len()..]) {
+                            Ok(limit) => self.rate_limiter.set_route(handler.clone(), Some(limit)),
+                            Err(err) => println!("Invalid `{}` for the route {}: {}", flag, route, err),
+                        },
+                        flag => println!("Unknown flag `{}` for the route {}", flag, route),
+                    }
+                }
+                (route, handler)
+            })
+            .collect();
         //load the route_tree
         self.load_route_tree(&raw_routes);
+        self.raw_routes.extend(raw_routes);
+    }
+
+    //imports the handler of every registered route, so no route is loaded lazily at request time.
+    //returns the routes whose module or function could not be loaded, with their handler and error
+    pub fn load_handlers(&mut self, py: Python) -> Vec<(String, String, PyErr)> {
+        let mut routes: Vec<(String, String)> = self.raw_routes.clone().into_iter().collect();
+        routes.sort();
+        let mut failures = Vec::new();
+        for (route, handler_path) in routes {
+            if let Err(err) = self.get_or_save_handler(py, handler_path.clone()) {
+                failures.push((route, handler_path, err));
+            }
+        }
+        failures
     }
 
     fn load_route_tree(&mut self, raw_routes: &Vec<(String, String)>) {
@@ -90,7 +180,7 @@ impl App {
             Some((left, right)) => {
                 let original_path = left.to_string();
                 let left = if left.starts_with("<") { "CUSTOM_PARAM" } else { left } ;
-                if left == "" { 
+                if left.is_empty() { 
                     return Self::recursive_insert(right, raw_path, tree);
                 } 
 
@@ -128,59 +218,57 @@ impl App {
         }
     }
 
+    //the client of the request, behind the trusted proxies the one they forwarded
+    pub fn resolve_client(&self, request: &mut Request) {
+        request.resolve_client(&self.trusted_proxies);
+    }
+
+    //takes a token for the request, checked by the server before it is queued so the clients
+    //over their limit never wait for a worker
+    pub fn check_rate_limit(&self, request: &Request) -> Option<RateLimitStatus> {
+        if !self.rate_limiter.is_enabled() {
+            return None;
+        }
+        let (handler_path, _) = self.resolve_route(&request.path);
+        self.rate_limiter.check(&request.client, &request.headers, handler_path.as_deref())
+    }
+
+    //the route is the decoded path of the request, without the query string
     pub fn resolve_route(&self, route: &str) -> (Option<String>, HashMap<String, String>) {
         //resolve the route, returning the the path of the handler
         //try to resolve the whole route first
         let mut path_variables = HashMap::new(); 
 
-        match self.routes_tree.childrens.get(route) {
-            Some(node) => return (node.handler.clone(), path_variables),
-            None => (),
-        };
-        
-        let (route, route_variables) = match route.split_once('?') { 
-            Some(split) => split,
-            None => (route, "") 
-        };
-
-        for route_var in route_variables.split('&') { 
-            match route_var.split_once('=') { 
-                Some((key, value)) => { 
-                    path_variables.insert(key.to_string(), value.to_string());
-                },
-                None => ()
-            } 
-        } 
+        if let Some(node) = self.routes_tree.childrens.get(route) {
+            return (node.handler.clone(), path_variables);
+        }
 
         //try to resolve the route one by one
         let mut actual_node = self.routes_tree.clone();
         let mut as_list: Vec<String> = route.split('/').map(|p| p.to_string()).collect();
         let _ =  as_list.remove(0);
         for (i, p) in as_list.iter().enumerate() {
-            match self.next_item_while_resolving(p, &actual_node, true) { 
-                Some(child_node) => { 
-                    let child_clone = child_node.clone();
-                    if child_clone.path.unwrap() == "CUSTOM_PARAM"{ 
-                        path_variables.insert(
-                            child_clone.original_path.unwrap(),
-                            p.to_string(),
-                        );
-                    } 
-                    if i == as_list.len() - 1 {
-                        return (child_node.handler.clone(), path_variables);
-                    }
-                    actual_node = child_node;
-                },
-                None => (),
+            if let Some(child_node) = self.next_item_while_resolving(p, &actual_node, true) { 
+                let child_clone = child_node.clone();
+                if child_clone.path.unwrap() == "CUSTOM_PARAM"{ 
+                    path_variables.insert(
+                        child_clone.original_path.unwrap(),
+                        p.to_string(),
+                    );
+                } 
+                if i == as_list.len() - 1 {
+                    return (child_node.handler.clone(), path_variables);
+                }
+                actual_node = child_node;
             } 
         }
         (None, path_variables) 
     }
     
-    fn next_item_while_resolving(&self, p: &str, node: &Box<RouteNode>, fallback: bool) -> Option<Box<RouteNode>> { 
+    fn next_item_while_resolving(&self, p: &str, node: &RouteNode, fallback: bool) -> Option<Box<RouteNode>> { 
         match node.childrens.get(p) {
             Some(children_node) => {
-                return Some(children_node.clone());
+                Some(children_node.clone())
             }
             None => {
                 if fallback { 
@@ -193,125 +281,307 @@ impl App {
         }
     } 
 
-    pub fn start(&mut self, project_path: &str, receiver: Receiver<(Option<TcpStream>, String)>){ 
-        self.initialize_application(project_path);
-        loop { 
-            let (socket, raw_request) = receiver.recv().unwrap();
-            match socket { 
-                None => { 
-                    //this will kill the thread
-                    break;
-                },
-                Some(mut socket) => { 
-                    unsafe { 
-                        Python::with_gil_unchecked(|py| {
-                            let result = self.process_request(py, raw_request); 
-
-                            //write the reponse
-                            socket.write_all(result.as_bytes()).unwrap();
-                            socket.flush().unwrap();
-                        });
-                    }
+    pub fn start(&mut self, queue: JobQueue){ 
+        //streamed responses keep the interpreter to get their chunks
+        let interpreter = Rc::new(self.load_interpreter());
+        //only the applications with timeouts get their handlers watched
+        let watchdog = match self.has_timeouts() { 
+            true => match interpreter.interrupter() { 
+                Ok(interrupter) => Some(Watchdog::start(interrupter, queue.clone())), 
+                Err(traceback) => { 
+                    interpreter.with_gil(|py| traceback.print(py));
+                    None
+                } 
+            }, 
+            false => None, 
+        };
+        //a None will kill the thread
+        while let Some(job) = queue.next() { 
+            self.process_job(&interpreter, job, &queue, watchdog.as_ref());
+        } 
+    } 
+
+    //creates the interpreter of this worker and loads the application in it
+    fn load_interpreter(&mut self) -> Interpreter { 
+        let project_path = self.config.project_path.clone();
+        let project_path = project_path.as_str();
+        let interpreter = Interpreter::new(self.config.worker_mode, project_path);
+        let import_failed = interpreter.with_gil(|py| {
+            match self.initialize_application(py, project_path) { 
+                Ok(_) => false, 
+                Err(traceback) => { 
+                    let import_failed = traceback.is_instance_of::<PyImportError>(py);
+                    traceback.print(py);
+                    import_failed
                 } 
             } 
+        });
+
+        if import_failed && interpreter.is_sub() { 
+            //some extension module of the project can not be loaded in a sub interpreter
+            println!("The application can not run in a sub interpreter, using the main interpreter");
+            drop(interpreter);
+            let interpreter = Interpreter::Main;
+            interpreter.with_gil(|py| {
+                if let Err(traceback) = self.initialize_application(py, project_path) { 
+                    traceback.print(py);
+                } 
+            });
+            return interpreter;
         } 
+        interpreter
     } 
 
 
-    pub fn process_request(&mut self, py: Python, raw_request: String)-> String {
-        //parse the raw request string to a request
-        let mut request = Request::from_string(raw_request);
+    fn process_job(&mut self, interpreter: &Rc<Interpreter>, job: Job, queue: &JobQueue, watchdog: Option<&Watchdog>) { 
+        //the client waited too long already, it is likely gone or retrying
+        let queue_timeout = self.config.queue_timeout.map(Duration::from_secs_f64);
+        if queue_timeout.map(|timeout| job.queued_at.elapsed() > timeout).unwrap_or(false) { 
+            return job.reply.send(overloaded(self.config.retry_after));
+        } 
+        //parsed by the server, with its client resolved
+        let mut request = job.request;
+        //the route is only looked up here for the websockets and the timeouts, the handler looks it up again
+        let needs_route = !self.websocket_handlers.is_empty() || watchdog.is_some();
+        let (handler_path, route_variables) = match needs_route { 
+            true => self.resolve_route(request.path.as_str()), 
+            false => (None, HashMap::new()), 
+        };
+        //websocket routes keep the connection, instead of answering it
+        if let Some(path) = handler_path.as_ref().filter(|path| self.websocket_handlers.contains(*path)) { 
+            request.path_variables = Some(route_variables);
+            return self.run_websocket(interpreter, request, path.clone(), job.reply, queue);
+        } 
+        //the deadline covers producing the response, not sending it
+        let timeout = handler_path.as_ref().and_then(|path| self.handler_timeout(path));
+        let watchdog = match (watchdog, timeout, &handler_path) { 
+            (Some(watchdog), Some(timeout), Some(path)) => { 
+                let description = format!("{:?} {} -> {}", request.method, request.path, path);
+                watchdog.arm(timeout, description, job.reply.fallback());
+                Some(watchdog)
+            } 
+            _ => None, 
+        };
+        let mut response = self.process_request(interpreter, request);
+        if let Some(watchdog) = watchdog { 
+            watchdog.disarm(interpreter);
+        } 
+        if let Some(status) = job.rate_limit { 
+            response = status.apply(response);
+        } 
+        //an event stream holds the worker like a websocket, for as long as the client listens
+        let _session = is_event_stream(response.header("Content-Type")).then(|| queue.session());
+
+        //send the reponse to the connection, the gil is only taken again for streamed bodies
+        job.reply.send(response);
+    } 
+
+    fn process_request(&mut self, interpreter: &Rc<Interpreter>, request: Request)-> Response {
+        //HEAD requests and conditional requests are answered from the response of the handler
+        let conditions = Conditions::from_request(&request);
+        let accept_encoding = request.headers.get("Accept-Encoding").cloned();
+        //static files never go through python
+        let response = match self.assets.serve(&request, &conditions) { 
+            Some(response) => response, 
+            None => interpreter.with_gil(|py| self.handle_request(py, request, interpreter)), 
+        };
+        //compressed without the gil, before the etag is computed from the body
+        let response = self.compression.apply(accept_encoding.as_deref(), response);
+        conditions.apply(response)
+    }
 
+    fn handle_request(&mut self, py: Python, mut request: Request, interpreter: &Rc<Interpreter>) -> Response {
         //get the handler path
         let (maybe_path, route_variables) = self.resolve_route(request.path.as_str());
         //get the handler (python function that is going to handle the request !
         match maybe_path{
             Some(path) => {
                 request.path_variables = Some(route_variables);
-                let handler = self.get_or_save_handler(py, path).unwrap();
+                let handler = match self.get_or_save_handler(py, path) { 
+                    Ok(handler) => handler, 
+                    Err(traceback) => { 
+                        return self.error_response(py, traceback, None);
+                    } 
+                };
                 //send the request to the handler and get the response
-                //handler not found yet, create find it.
                 
-                self.execute_request(&py, &handler, request).unwrap()
+                self.execute_request(py, &handler, request, interpreter)
             }
             None => {
-                "".to_string()
+                Response::new(404, "text/html", "")
             }
         }
     }
 
-    fn initialize_application(&mut self, path: &str) {
-        let mut file_name = path.to_string();
-        file_name.push_str("/fastry.py");
-        let mut file = File::open(file_name.clone()).unwrap();
-        let mut code = String::new();
-        file.read_to_string(&mut code).unwrap();
-        println!("{}", file_name);
-        unsafe { 
-            Python::with_gil_unchecked(|py| {
-                let res = PyModule::from_code(py, code.as_str(), file_name.as_str(), path);
-                match res { 
-                    Ok(module) => { 
-                        let application: PyObject = module.getattr("FastryApplication").unwrap().into();
-                        let python_application: PyObject = application.call0(py).unwrap();
-                        self.python_app = Some(python_application);
-                    },
-                    Err(traceback) => { 
-                        traceback.print(py);
-                    } 
+    fn has_timeouts(&self) -> bool { 
+        self.config.handler_timeout.is_some() || self.handler_timeouts.values().any(Option::is_some)
+    } 
+
+    //the timeout of the route, or the one of the config
+    fn handler_timeout(&self, handler_path: &str) -> Option<Duration> { 
+        match self.handler_timeouts.get(handler_path) { 
+            Some(timeout) => *timeout, 
+            None => self.config.handler_timeout.map(Duration::from_secs_f64), 
+        } 
+    } 
+
+    //accepts the websocket and gives it to the handler, along with the application and the request
+    fn run_websocket(&mut self, interpreter: &Rc<Interpreter>, request: Request, path: String, reply: Reply, queue: &JobQueue) { 
+        let accept = match websocket_accept(&request) { 
+            Ok(accept) => accept, 
+            Err(response) => return reply.send(response), 
+        };
+        let handler = match interpreter.with_gil(|py| self.get_or_save_handler(py, path)) { 
+            Ok(handler) => handler, 
+            Err(traceback) => return reply.send(interpreter.with_gil(|py| self.error_response(py, traceback, None))), 
+        };
+        let (peer, bridge) = websocket_channel();
+        if !reply.upgrade(switching_protocols(&accept), bridge) { 
+            return;
+        } 
+        //the other workers take the requests while this one holds the websocket
+        let _session = queue.session();
+        interpreter.with_gil(|py| { 
+            let socket = match Py::new(py, PyWebSocket::new(peer)) { 
+                Ok(socket) => socket, 
+                Err(traceback) => return traceback.print(py), 
+            };
+            let limits = BodyLimits::from_config(&self.config);
+            let result = Py::new(py, PyRequest::new(request, limits, self.cookie_key.clone()))
+                .and_then(|request| handler.call1(py, (self.python_app.clone().unwrap(), request, socket.clone_ref(py))))
+                .and_then(|res| Self::run_coroutine(py, res));
+            //the client only gets the close code of the error, it is logged with its id
+            let code = match result { 
+                Ok(_) => 1000, 
+                Err(traceback) => { 
+                    self.error_response(py, traceback, None);
+                    1011
                 } 
-            });
+            };
+            socket.borrow(py).finish(py, code);
+        });
+    } 
+
+    //the async handlers run in an event loop of their own, on this worker
+    fn run_coroutine(py: Python, res: PyObject) -> PyResult<PyObject> { 
+        let asyncio = py.import("asyncio")?;
+        if !asyncio.call_method1("iscoroutine", (res.as_ref(py), ))?.is_true()? { 
+            return Ok(res);
         } 
+        Ok(asyncio.call_method1("run", (res, ))?.into())
     } 
 
-    fn get_or_save_handler(&mut self, py: Python ,path: String) -> Option<PyObject> { 
-        let (file_name, fn_name) = path.split_once("::").unwrap();
-        match self.handlers.get(&(file_name.to_string(), fn_name.to_string())) { 
-            Some(handler) => Some(handler.clone()), 
+    fn initialize_application(&mut self, py: Python, path: &str) -> PyResult<()> {
+        //the project root is in sys.path, so `fastry.py` is imported like the handler modules
+        println!("{}/fastry.py", path);
+        let module = py.import("fastry")?;
+        let application: PyObject = module.getattr("FastryApplication")?.into();
+        let python_application: PyObject = application.call0(py)?;
+        self.python_app = Some(python_application);
+
+        //the routes were checked at startup, a failure here comes from this interpreter
+        match self.load_handlers(py).into_iter().next() { 
+            Some((_, _, err)) => Err(err), 
+            None => Ok(()), 
+        } 
+    } 
+
+    fn get_or_save_handler(&mut self, py: Python ,path: String) -> PyResult<PyObject> { 
+        let (module_name, fn_name) = path.split_once("::").unwrap();
+        let key = (module_name.to_string(), fn_name.to_string());
+        match self.handlers.get(&key) { 
+            Some(handler) => Ok(handler.clone()), 
             None => { 
-                let mut file = File::open(file_name).unwrap();
-                let mut code = String::new();
-                _ = file.read_to_string(&mut code);
-                let module = PyModule::from_code(py, code.as_str(), "project/app.py", "app").unwrap();
-                let handler: PyObject = module.getattr(fn_name).unwrap().into();
-                self.handlers.insert((file_name.to_string(), fn_name.to_string()), handler.clone());
-                Some(handler) 
+                //imported modules are cached in sys.modules, so each one is executed once per interpreter
+                let module = py.import("importlib")?.call_method1("import_module", (module_name, ))?;
+                let handler: PyObject = module.getattr(fn_name)?.into();
+                self.handlers.insert(key, handler.clone());
+                Ok(handler) 
             } 
         }  
     } 
 
-    fn execute_request(&self, py: &Python, handler: &PyObject, request: Request) -> PyResult<String> {
-        //process the headers and body
-        let processed_request = ProcessedRequest::from_request(request);
+    fn execute_request(&self, py: Python, handler: &PyObject, request: Request, interpreter: &Rc<Interpreter>) -> Response {
+        //the body is parsed by the request object, when the handler reads it
+        let limits = BodyLimits::from_config(&self.config);
+        let request = match Py::new(py, PyRequest::new(request, limits, self.cookie_key.clone())) { 
+            Ok(request) => request, 
+            Err(traceback) => return self.error_response(py, traceback, None), 
+        };
 
         //send this request to the python handler
-        match handler.call1(*py,(
-            self.python_app.clone().unwrap(),
-            //convert to dict, the processed request
-            pythonize(*py, &processed_request).unwrap()
-        )) { 
-            Ok(res) => { 
-                let code: i32 = res.getattr(*py, "code")?.extract(*py)?;        
-                let _type: String = res.getattr(*py, "type")?.extract(*py)?;        
-                let body: String = res.getattr(*py, "body")?.extract(*py)?;        
-        
-                Ok(format!(
-                    "HTTP/1.1 {} OK\r\nDate: {:?}\r\nServer: Someserver\r\nContent-Length: {}\r\nContent-Type: {}\r\nConnection: close\r\n\r\n\r\n{}", 
-                    code, Instant::now(), body.len() + 2, _type, body, 
-                )) 
-            },
+        let result = handler
+            .call1(py, (self.python_app.clone().unwrap(), request.clone_ref(py)))
+            .and_then(|res| Self::build_response(py, res, interpreter));
+        match result { 
+            Ok(response) => response,
             Err(traceback) => { 
-                traceback.print(*py);
-                let code: i32 = 500;        
-                let _type: &str = "text/html";        
-                let body: &str = "";        
-        
-                Ok(format!(
-                    "HTTP/1.1 {} OK\r\nDate: {:?}\r\nServer: Someserver\r\nContent-Length: {}\r\nContent-Type: {}\r\nConnection: close\r\n\r\n\r\n{}", 
-                    code, Instant::now(), body.len() + 2, _type, body, 
-                )) 
+                let request = request.borrow(py);
+                //the handler did not handle a body it could not parse, the client sent it wrong
+                if traceback.is_instance_of::<BadRequest>(py) { 
+                    if let Some(err) = request.body_error() { 
+                        return err.response();
+                    } 
+                } 
+                self.error_response(py, traceback, Some(request.request()))
             } 
         } 
     }
-}
 
+    //converts the object returned by the handler to the response sent to the client
+    fn build_response(py: Python, res: PyObject, interpreter: &Rc<Interpreter>) -> PyResult<Response> { 
+        let code: i32 = res.getattr(py, "code")?.extract(py)?;        
+        let _type: String = res.getattr(py, "type")?.extract(py)?;        
+        let body = res.getattr(py, "body")?;
+        let body = body.as_ref(py);
+
+        //the body is a str, bytes, or an iterator of them streamed to the client
+        let mut response = if let Ok(body) = body.downcast::<PyString>() { 
+            Response::new(code, &_type, body.to_str()?)
+        } else if let Ok(body) = body.downcast::<PyBytes>() { 
+            Response::new(code, &_type, body.as_bytes())
+        } else if is_event_stream(Some(&_type)) { 
+            let stream = PyStream::new(py, body, interpreter.clone())?.events();
+            Response::stream(code, &_type, Box::new(stream))
+        } else { 
+            let stream = PyStream::new(py, body, interpreter.clone())?;
+            Response::stream(code, &_type, Box::new(stream))
+        };
+        //the extra headers, like `Set-Cookie`, are optional: a dict or a list of pairs
+        if let Ok(headers) = res.getattr(py, "headers") { 
+            let headers = headers.as_ref(py);
+            let headers: Vec<(String, String)> = match headers.downcast::<PyDict>() { 
+                Ok(headers) => headers.items().extract()?,
+                Err(_) if headers.is_none() => Vec::new(),
+                Err(_) => headers.extract()?,
+            };
+            for (key, value) in headers { 
+                if key.contains(['\r', '\n', ':']) || value.contains(['\r', '\n']) { 
+                    return Err(PyValueError::new_err(format!("invalid response header {:?}", key)));
+                } 
+                response = response.with_header(&key, &value);
+            } 
+        } 
+        //the proxies and the browsers must not hold the events back
+        if is_event_stream(Some(&_type)) { 
+            for (key, value) in [("Cache-Control", "no-cache"), ("X-Accel-Buffering", "no")] { 
+                if response.header(key).is_none() { 
+                    response = response.with_header(key, value);
+                } 
+            } 
+        } 
+        Ok(response)
+    } 
+
+    //logs the error with its id, in debug mode the client gets the whole report
+    fn error_response(&self, py: Python, traceback: PyErr, request: Option<&Request>) -> Response { 
+        let report = ErrorReport::from_error(py, &traceback);
+        println!("Error {}:", report.id);
+        traceback.print(py);
+        if self.config.debug { 
+            report.debug_response(request)
+        } else { 
+            report.generic_response()
+        } 
+    } 
+}
@@ -1,3 +1,10 @@
+#[allow(clippy::module_inception)]
 pub mod app;
+pub mod stream;
+pub mod watchdog;
+pub mod websocket;
 
 pub use app::*;
+pub use stream::*;
+pub use watchdog::*;
+pub use websocket::*;
@@ -0,0 +1,148 @@
+extern crate pyo3;
+
+use crate::interpreter::Interpreter;
+use crate::response::{ChunkSource, Event};
+use pyo3::exceptions::{PyStopAsyncIteration, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyString};
+use std::io;
+use std::rc::Rc;
+
+//the body of a handler returning an iterator, every item is a chunk sent to the client.
+//the gil is only taken to get the next item, never while the chunk is written
+pub struct PyStream {
+    //dropped with the gil of its interpreter
+    iterator: Option<PyObject>,
+    //async iterators are advanced in an event loop of their own
+    event_loop: Option<PyObject>,
+    //the items are server-sent events, formatted here
+    events: bool,
+    interpreter: Rc<Interpreter>,
+}
+
+impl PyStream {
+    pub fn new(py: Python, body: &PyAny, interpreter: Rc<Interpreter>) -> PyResult<Self> {
+        let (iterator, event_loop) = match body.hasattr("__aiter__")? {
+            true => {
+                let event_loop = py.import("asyncio")?.call_method0("new_event_loop")?;
+                (body.call_method0("__aiter__")?, Some(event_loop.into_py(py)))
+            }
+            false => (body.iter()?.as_ref(), None),
+        };
+        Ok(Self {
+            iterator: Some(iterator.into_py(py)),
+            event_loop,
+            events: false,
+            interpreter,
+        })
+    }
+
+    //every item is an event: a str with the data, or a dict (or an object) with `data`,
+    //`event`, `id`, `retry` and `comment`
+    pub fn events(mut self) -> Self {
+        self.events = true;
+        self
+    }
+
+    fn next_item<'py>(&self, py: Python<'py>, iterator: &'py PyAny) -> PyResult<Option<&'py PyAny>> {
+        let event_loop = match &self.event_loop {
+            Some(event_loop) => event_loop.clone_ref(py).into_ref(py),
+            None => return iterator.iter()?.next().transpose(),
+        };
+        let next = iterator.call_method0("__anext__")?;
+        match event_loop.call_method1("run_until_complete", (next,)) {
+            Ok(item) => Ok(Some(item)),
+            Err(err) if err.is_instance_of::<PyStopAsyncIteration>(py) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn chunk(&self, py: Python, item: &PyAny) -> PyResult<Vec<u8>> {
+        if self.events {
+            let event = to_event(py, item)?;
+            event.validate().map_err(PyValueError::new_err)?;
+            return Ok(event.to_bytes());
+        }
+        if let Ok(text) = item.downcast::<PyString>() {
+            return Ok(text.to_str()?.as_bytes().to_vec());
+        }
+        if let Ok(bytes) = item.downcast::<PyBytes>() {
+            return Ok(bytes.as_bytes().to_vec());
+        }
+        Err(PyTypeError::new_err("streamed chunks must be str or bytes"))
+    }
+}
+
+fn to_event(py: Python, item: &PyAny) -> PyResult<Event> {
+    if let Ok(text) = item.downcast::<PyString>() {
+        return Ok(Event {
+            data: Some(text.to_str()?.to_string()),
+            ..Default::default()
+        });
+    }
+    let field = |name: &str| -> PyResult<Option<&PyAny>> {
+        let value = match item.downcast::<PyDict>() {
+            Ok(dict) => dict.get_item(name),
+            Err(_) => item.getattr(name).ok(),
+        };
+        Ok(value.filter(|value| !value.is_none()))
+    };
+    let text = |name: &str| -> PyResult<Option<String>> { field(name)?.map(|value| Ok(value.str()?.to_string())).transpose() };
+    //the data that is not text is sent as json
+    let data = match field("data")? {
+        Some(data) if data.is_instance_of::<PyString>()? => Some(data.extract()?),
+        Some(data) => Some(py.import("json")?.call_method1("dumps", (data,))?.extract()?),
+        None => None,
+    };
+    Ok(Event {
+        event: text("event")?,
+        data,
+        id: text("id")?,
+        retry: field("retry")?.map(|retry| retry.extract()).transpose()?,
+        comment: text("comment")?,
+    })
+}
+
+impl ChunkSource for PyStream {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let iterator = match &self.iterator {
+            Some(iterator) => iterator,
+            None => return Ok(None),
+        };
+        self.interpreter.with_gil(|py| {
+            let chunk = self
+                .next_item(py, iterator.as_ref(py))
+                .and_then(|item| item.map(|item| self.chunk(py, item)).transpose());
+            match chunk {
+                Ok(chunk) => Ok(chunk),
+                Err(traceback) => {
+                    //the status was already sent, the client only sees the body stop
+                    println!("Error while streaming the response:");
+                    traceback.print(py);
+                    Err(io::Error::other("the handler stream failed"))
+                }
+            }
+        })
+    }
+}
+
+impl Drop for PyStream {
+    fn drop(&mut self) {
+        let iterator = self.iterator.take();
+        let event_loop = self.event_loop.take();
+        self.interpreter.with_gil(|py| {
+            //the async generators left halfway run their cleanup before the loop is gone
+            if let Some(event_loop) = event_loop {
+                let event_loop = event_loop.as_ref(py);
+                let closed = event_loop
+                    .call_method0("shutdown_asyncgens")
+                    .and_then(|shutdown| event_loop.call_method1("run_until_complete", (shutdown,)))
+                    .and_then(|_| event_loop.call_method0("close"));
+                if let Err(traceback) = closed {
+                    traceback.print(py);
+                }
+            }
+            drop(iterator);
+        });
+    }
+}
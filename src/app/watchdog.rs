@@ -0,0 +1,176 @@
+use crate::interpreter::{Interpreter, Interrupter};
+use crate::response::Response;
+use crate::server::{JobQueue, Reply, Session};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//watches the handler a worker is running, on its own thread. when the deadline passes the client
+//gets a 504 and the handler a TimeoutError
+pub struct Watchdog {
+    shared: Arc<Shared>,
+    interrupter: Interrupter,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wakeup: Condvar,
+    //notified once the timeout is raised in the handler
+    raised: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    armed: Option<Armed>,
+    //the timeout is being raised without the lock, the handler can not be disarmed meanwhile
+    raising: bool,
+    stopped: bool,
+}
+
+struct Armed {
+    deadline: Instant,
+    timeout: Duration,
+    //what is logged, like `GET /slow -> app::slow`
+    description: String,
+    //taken to send the 504
+    reply: Option<Reply>,
+    //the worker counts as taken from the timeout until the handler gives up
+    fired: Option<Session>,
+}
+
+impl Watchdog {
+    pub fn start(interrupter: Interrupter, queue: JobQueue) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            wakeup: Condvar::new(),
+            raised: Condvar::new(),
+        });
+        let watched = shared.clone();
+        thread::spawn(move || watch(&watched, interrupter, &queue));
+        Self { shared, interrupter }
+    }
+
+    pub fn arm(&self, timeout: Duration, description: String, reply: Reply) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.armed = Some(Armed {
+            deadline: Instant::now() + timeout,
+            timeout,
+            description,
+            reply: Some(reply),
+            fired: None,
+        });
+        self.shared.wakeup.notify_one();
+    }
+
+    //it has to be called without the gil, the watchdog takes it to interrupt the handler
+    pub fn disarm(&self, interpreter: &Interpreter) {
+        let armed = {
+            let mut state = self.shared.state.lock().unwrap();
+            //an error raised after being cleared would reach the next handler
+            while state.raising {
+                state = self.shared.raised.wait(state).unwrap();
+            }
+            state.armed.take()
+        };
+        //the handler can be done before running the python code that would raise the error
+        if armed.map(|armed| armed.fired.is_some()).unwrap_or(false) {
+            interpreter.with_gil(|py| self.interrupter.clear(py));
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.wakeup.notify_one();
+    }
+}
+
+fn watch(shared: &Shared, interrupter: Interrupter, queue: &JobQueue) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if state.stopped {
+            return;
+        }
+        let deadline = match &state.armed {
+            Some(armed) if armed.fired.is_none() => armed.deadline,
+            _ => {
+                state = shared.wakeup.wait(state).unwrap();
+                continue;
+            }
+        };
+        let now = Instant::now();
+        if now < deadline {
+            state = shared.wakeup.wait_timeout(state, deadline - now).unwrap().0;
+            continue;
+        }
+        let armed = state.armed.as_mut().unwrap();
+        println!(
+            "Timeout: {} took more than {:.1}s, interrupting it",
+            armed.description,
+            armed.timeout.as_secs_f64()
+        );
+        let reply = armed.reply.take();
+        armed.fired = Some(queue.session());
+        state.raising = true;
+        //sending waits for the client and raising takes the gil, so neither is done with the lock
+        drop(state);
+        if let Some(reply) = reply {
+            reply.send(Response::new(504, "text/plain; charset=utf-8", "Gateway Timeout"));
+        }
+        interrupter.raise_timeout();
+        state = shared.state.lock().unwrap();
+        state.raising = false;
+        shared.raised.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{JobQueue, ReplyEvent};
+    use pyo3::exceptions::PyTimeoutError;
+    use std::sync::mpsc;
+
+    //runs the code as a handler with the timeout, returns the response sent to the client if any
+    //and whether the handler got a TimeoutError
+    fn handle(code: &'static str, timeout: Duration) -> (Option<i32>, bool) {
+        pyo3::prepare_freethreaded_python();
+        thread::spawn(move || {
+            let interpreter = Interpreter::Main;
+            let (_sender, receiver) = mpsc::sync_channel(1);
+            let watchdog = Watchdog::start(interpreter.interrupter().unwrap(), JobQueue::new(receiver));
+            let (reply, mut replies) = Reply::channel();
+            watchdog.arm(timeout, "GET /slow -> app::slow".to_string(), reply.fallback());
+            let timed_out = interpreter.with_gil(|py| match py.run(code, None, None) {
+                Ok(()) => false,
+                Err(err) => err.is_instance_of::<PyTimeoutError>(py),
+            });
+            watchdog.disarm(&interpreter);
+            reply.send(Response::new(200, "text/plain", "done"));
+            let code = match replies.blocking_recv() {
+                Some(ReplyEvent::Head { head, .. }) => Some(head.code),
+                _ => None,
+            };
+            (code, timed_out)
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn answers_a_slow_handler_with_a_504() {
+        let started = Instant::now();
+        let (code, timed_out) = handle("import time\nfor _ in range(100):\n    time.sleep(0.05)", Duration::from_millis(200));
+        assert_eq!(code, Some(504));
+        assert!(timed_out);
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn leaves_a_fast_handler_alone() {
+        let (code, timed_out) = handle("import time\ntime.sleep(0.05)", Duration::from_secs(5));
+        assert_eq!(code, Some(200));
+        assert!(!timed_out);
+    }
+}
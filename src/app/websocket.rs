@@ -0,0 +1,199 @@
+extern crate pyo3;
+extern crate tungstenite;
+
+use crate::request::{Request, RequestMethod};
+use crate::response::{Body, Response};
+use crate::server::{WebSocketMessage, WebSocketPeer};
+use pyo3::exceptions::{PyConnectionError, PyTimeoutError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Mutex;
+use std::time::Duration;
+use tungstenite::handshake::derive_accept_key;
+
+pyo3::create_exception!(fastry, ConnectionClosed, PyConnectionError);
+
+//the close code when the connection dropped without a close frame
+const ABNORMAL_CLOSURE: u16 = 1006;
+//a close frame has room for 125 bytes, the code takes 2 of them
+const MAX_CLOSE_REASON: usize = 123;
+
+//checks the opening handshake of the client, the error is the response refusing it
+pub fn websocket_accept(request: &Request) -> Result<String, Response> {
+    let has_token = |name: &str, token: &str| {
+        request
+            .headers
+            .get_all(name)
+            .iter()
+            .any(|value| value.split(',').any(|item| item.trim().eq_ignore_ascii_case(token)))
+    };
+    let is_upgrade = matches!(request.method, RequestMethod::GET)
+        && request.http_version == "HTTP/1.1"
+        && has_token("Upgrade", "websocket")
+        && has_token("Connection", "upgrade");
+    if !is_upgrade {
+        return Err(Response::new(426, "text/plain; charset=utf-8", "Upgrade Required").with_header("Upgrade", "websocket"));
+    }
+    if request.headers.get("Sec-WebSocket-Version").map(|version| version.trim()) != Some("13") {
+        return Err(Response::new(426, "text/plain; charset=utf-8", "Unsupported WebSocket Version")
+            .with_header("Sec-WebSocket-Version", "13"));
+    }
+    match request.headers.get("Sec-WebSocket-Key").map(|key| key.trim()) {
+        Some(key) if !key.is_empty() => Ok(derive_accept_key(key.as_bytes())),
+        _ => Err(Response::new(400, "text/plain; charset=utf-8", "missing Sec-WebSocket-Key")),
+    }
+}
+
+//the response switching the connection to the websocket protocol
+pub fn switching_protocols(accept: &str) -> Response {
+    Response {
+        code: 101,
+        headers: vec![
+            ("Upgrade".to_string(), "websocket".to_string()),
+            ("Connection".to_string(), "Upgrade".to_string()),
+            ("Sec-WebSocket-Accept".to_string(), accept.to_string()),
+        ],
+        body: Body::Bytes(Vec::new()),
+        omit_body: false,
+    }
+}
+
+//the websocket given to the handlers. the calls wait without the gil, so other threads
+//(or the executor of an event loop) can receive and send at the same time
+#[pyclass(name = "WebSocket")]
+pub struct PyWebSocket {
+    peer: WebSocketPeer,
+    //the code and the reason of the close, once the connection is closed
+    closed: Mutex<Option<(u16, String)>>,
+}
+
+impl PyWebSocket {
+    pub fn new(peer: WebSocketPeer) -> Self {
+        Self {
+            peer,
+            closed: Mutex::new(None),
+        }
+    }
+
+    //closes the connection when the handler returned without closing it
+    pub fn finish(&self, py: Python, code: u16) {
+        let _ = self.close(py, code, "");
+    }
+
+    fn closed_with(&self, code: u16, reason: String) {
+        self.closed.lock().unwrap().get_or_insert((code, reason));
+    }
+}
+
+#[pymethods]
+impl PyWebSocket {
+    //the next message of the client, a str or bytes. None once the connection is closed
+    #[args(timeout = "None")]
+    fn receive(&self, py: Python, timeout: Option<f64>) -> PyResult<Option<PyObject>> {
+        if self.closed.lock().unwrap().is_some() {
+            return Ok(None);
+        }
+        let timeout = match timeout {
+            Some(timeout) if timeout < 0.0 || !timeout.is_finite() => {
+                return Err(PyValueError::new_err("timeout must be a positive number"))
+            }
+            timeout => timeout.map(Duration::from_secs_f64),
+        };
+        match py.allow_threads(|| self.peer.receive(timeout)) {
+            Ok(WebSocketMessage::Text(text)) => Ok(Some(text.into_py(py))),
+            Ok(WebSocketMessage::Binary(data)) => Ok(Some(PyBytes::new(py, &data).into())),
+            Ok(WebSocketMessage::Close(code, reason)) => {
+                self.closed_with(code, reason);
+                Ok(None)
+            }
+            Err(RecvTimeoutError::Timeout) => Err(PyTimeoutError::new_err("no message received in time")),
+            Err(RecvTimeoutError::Disconnected) => {
+                self.closed_with(ABNORMAL_CLOSURE, String::new());
+                Ok(None)
+            }
+        }
+    }
+
+    //sends a str as a text message and bytes as a binary one
+    fn send(&self, py: Python, message: &PyAny) -> PyResult<()> {
+        let message = if let Ok(text) = message.downcast::<PyString>() {
+            WebSocketMessage::Text(text.to_str()?.to_string())
+        } else if let Ok(data) = message.downcast::<PyBytes>() {
+            WebSocketMessage::Binary(data.as_bytes().to_vec())
+        } else {
+            return Err(PyTypeError::new_err("websocket messages must be str or bytes"));
+        };
+        if self.closed.lock().unwrap().is_some() {
+            return Err(ConnectionClosed::new_err("the websocket is closed"));
+        }
+        match py.allow_threads(|| self.peer.send(message)) {
+            true => Ok(()),
+            false => {
+                self.closed_with(ABNORMAL_CLOSURE, String::new());
+                Err(ConnectionClosed::new_err("the websocket is closed"))
+            }
+        }
+    }
+
+    //starts the closing handshake, the messages received afterwards are dropped
+    #[args(code = "1000", reason = "\"\"")]
+    fn close(&self, py: Python, code: u16, reason: &str) -> PyResult<()> {
+        let valid_code = matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999);
+        if !valid_code {
+            return Err(PyValueError::new_err(format!("invalid close code {}", code)));
+        }
+        if reason.len() > MAX_CLOSE_REASON {
+            return Err(PyValueError::new_err("the close reason can not be longer than 123 bytes"));
+        }
+        {
+            let mut closed = self.closed.lock().unwrap();
+            if closed.is_some() {
+                return Ok(());
+            }
+            *closed = Some((code, reason.to_string()));
+        }
+        py.allow_threads(|| self.peer.send(WebSocketMessage::Close(code, reason.to_string())));
+        Ok(())
+    }
+
+    //awaitable versions of receive and send, for the async handlers. they wait in the
+    //default executor of the running loop
+    #[args(timeout = "None")]
+    fn receive_async(slf: &PyCell<Self>, py: Python, timeout: Option<f64>) -> PyResult<PyObject> {
+        let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+        let receive = slf.getattr("receive")?;
+        Ok(event_loop
+            .call_method1("run_in_executor", (py.None(), receive, timeout))?
+            .into())
+    }
+
+    fn send_async(slf: &PyCell<Self>, py: Python, message: &PyAny) -> PyResult<PyObject> {
+        let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+        let send = slf.getattr("send")?;
+        Ok(event_loop.call_method1("run_in_executor", (py.None(), send, message))?.into())
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.closed.lock().unwrap().is_some()
+    }
+
+    //1005 when the client closed without a code, 1006 when the connection dropped
+    #[getter]
+    fn close_code(&self) -> Option<u16> {
+        self.closed.lock().unwrap().as_ref().map(|(code, _)| *code)
+    }
+
+    #[getter]
+    fn close_reason(&self) -> Option<String> {
+        self.closed.lock().unwrap().as_ref().map(|(_, reason)| reason.clone())
+    }
+
+    fn __repr__(&self) -> String {
+        match self.close_code() {
+            Some(code) => format!("<WebSocket closed {}>", code),
+            None => "<WebSocket open>".to_string(),
+        }
+    }
+}
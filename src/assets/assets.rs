@@ -0,0 +1,462 @@
+extern crate mime_guess;
+extern crate percent_encoding;
+
+use crate::config::Config;
+use crate::request::{Request, RequestMethod};
+use crate::response::{accepts_encoding, escape_html, http_date, Body, Conditions, Response};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::cmp::Reverse;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+//the precompressed variants looked for next to every file, by content coding and extension
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+//a static mount, with its directory resolved
+#[derive(Debug, Clone)]
+struct Mount {
+    //the url prefix, without the trailing slash
+    url: String,
+    root: PathBuf,
+    index: Vec<String>,
+    listing: bool,
+    dotfiles: bool,
+}
+
+//the static files served by the workers before looking for a route, the gil is never taken for them
+#[derive(Debug, Clone, Default)]
+pub struct Assets {
+    mounts: Vec<Mount>,
+}
+
+impl Assets {
+    pub fn from_config(config: &Config) -> Self {
+        let mut mounts = Vec::new();
+        for mount in &config.static_mounts {
+            let root = Path::new(&config.project_path).join(&mount.directory);
+            match root.canonicalize() {
+                Ok(root) => mounts.push(Mount {
+                    url: mount.url.trim_end_matches('/').to_string(),
+                    root,
+                    index: mount.index.clone(),
+                    listing: mount.listing,
+                    dotfiles: mount.dotfiles,
+                }),
+                Err(err) => println!("Not serving {}: {}", root.display(), err),
+            }
+        }
+        //the most specific mount wins
+        mounts.sort_by_key(|mount| Reverse(mount.url.len()));
+        Self { mounts }
+    }
+
+    //the response to the requests under a mount, None for the others
+    pub fn serve(&self, request: &Request, conditions: &Conditions) -> Option<Response> {
+        let (mount, rest) = self
+            .mounts
+            .iter()
+            .find_map(|mount| mount.strip(&request.path).map(|rest| (mount, rest)))?;
+        if !matches!(request.method, RequestMethod::GET | RequestMethod::HEAD) {
+            let response = Response::new(405, "text/plain; charset=utf-8", "Method Not Allowed");
+            return Some(response.with_header("Allow", "GET, HEAD"));
+        }
+        //the mount owns its prefix, missing files never reach the routes
+        let response = mount.serve(rest, request, conditions);
+        Some(response.unwrap_or_else(|| Response::new(404, "text/plain; charset=utf-8", "Not Found")))
+    }
+}
+
+impl Mount {
+    //the rest of the path, if it is under this mount
+    fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(self.url.as_str())?;
+        if rest.is_empty() || rest.starts_with('/') {
+            Some(rest)
+        } else {
+            None
+        }
+    }
+
+    fn serve(&self, rest: &str, request: &Request, conditions: &Conditions) -> Option<Response> {
+        //the path is already decoded, so encoded dots and slashes are checked too
+        let mut path = self.root.clone();
+        for segment in rest.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return None,
+                segment if segment.contains(['\\', '\0', ':']) => return None,
+                //hidden files, like `.env` or `.git`, are not served unless the mount allows them
+                segment if segment.starts_with('.') && !self.dotfiles => return None,
+                segment => path.push(segment),
+            }
+        }
+        //symbolic links can not lead out of the directory either
+        let path = path.canonicalize().ok()?;
+        if !path.starts_with(&self.root) {
+            return None;
+        }
+
+        if !path.is_dir() {
+            return serve_file(&path, request, conditions);
+        }
+        //relative links of the index only work from a path ending with a slash
+        if !request.path.ends_with('/') {
+            let location = match request.target.split_once('?') {
+                Some((target, query)) => format!("{}/?{}", target, query),
+                None => format!("{}/", request.target),
+            };
+            return Some(Response::new(301, "text/plain; charset=utf-8", "").with_header("Location", &location));
+        }
+        for index in &self.index {
+            let index = path.join(index);
+            if index.is_file() {
+                return serve_file(&index, request, conditions);
+            }
+        }
+        if self.listing {
+            return listing(&path, &request.path, self.dotfiles);
+        }
+        None
+    }
+}
+
+fn serve_file(path: &Path, request: &Request, conditions: &Conditions) -> Option<Response> {
+    //a precompressed variant is sent when the client accepts it
+    let accept_encoding = request.headers.get("Accept-Encoding").map(|header| header.as_str()).unwrap_or("");
+    let mut has_variants = false;
+    let mut selected: (PathBuf, Option<&str>) = (path.to_path_buf(), None);
+    for (coding, extension) in PRECOMPRESSED {
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(".");
+        variant.push(extension);
+        let variant = PathBuf::from(variant);
+        if variant.is_file() {
+            has_variants = true;
+            if selected.1.is_none() && accepts_encoding(accept_encoding, coding) {
+                selected = (variant, Some(coding));
+            }
+        }
+    }
+    let (file_path, coding) = selected;
+
+    let file = File::open(&file_path).ok()?;
+    let metadata = file.metadata().ok()?;
+    let length = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_secs() as i64)
+        .unwrap_or(0);
+    //every variant has its own etag
+    let etag = match coding {
+        Some(coding) => format!("\"{:x}-{:x}-{}\"", length, modified, coding),
+        None => format!("\"{:x}-{:x}\"", length, modified),
+    };
+    let last_modified = http_date(modified);
+
+    let mut response = Response::file(200, &content_type(path), file, 0, length)
+        .with_header("ETag", &etag)
+        .with_header("Last-Modified", &last_modified)
+        .with_header("Accept-Ranges", "bytes");
+    if let Some(coding) = coding {
+        response = response.with_header("Content-Encoding", coding);
+    }
+    if has_variants {
+        response = response.with_header("Vary", "Accept-Encoding");
+    }
+    //a 304 is answered before looking at the range
+    if conditions.is_not_modified(&response) {
+        return Some(response);
+    }
+
+    let range = match request.headers.get("Range") {
+        Some(range) => range,
+        None => return Some(response),
+    };
+    //the range is only valid for the version of the file the client has
+    if let Some(if_range) = request.headers.get("If-Range") {
+        let if_range = if_range.trim();
+        if if_range != etag && if_range != last_modified {
+            return Some(response);
+        }
+    }
+    match parse_range(range, length) {
+        Ok(Some((start, end))) => {
+            response.code = 206;
+            if let Body::File { offset, length, .. } = &mut response.body {
+                *offset = start;
+                *length = end - start + 1;
+            }
+            let content_range = format!("bytes {}-{}/{}", start, end, length);
+            Some(response.with_header("Content-Range", &content_range))
+        }
+        Ok(None) => Some(response),
+        Err(()) => {
+            let content_range = format!("bytes */{}", length);
+            let response = Response::new(416, "text/plain; charset=utf-8", "Range Not Satisfiable");
+            Some(response.with_header("Content-Range", &content_range))
+        }
+    }
+}
+
+//the first and last byte of a single `bytes` range. other units, several ranges or an invalid
+//syntax are ignored and the whole file is sent, ranges outside of the file are an error
+fn parse_range(range: &str, length: u64) -> Result<Option<(u64, u64)>, ()> {
+    let range = match range.trim().split_once('=') {
+        Some((unit, range)) if unit.trim().eq_ignore_ascii_case("bytes") => range.trim(),
+        _ => return Ok(None),
+    };
+    if range.contains(',') {
+        return Ok(None);
+    }
+    let (start, end) = match range.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        //the last bytes of the file
+        let suffix: u64 = match end.parse() {
+            Ok(suffix) => suffix,
+            Err(_) => return Ok(None),
+        };
+        if suffix == 0 || length == 0 {
+            return Err(());
+        }
+        return Ok(Some((length.saturating_sub(suffix), length - 1)));
+    }
+    let start: u64 = match start.parse() {
+        Ok(start) => start,
+        Err(_) => return Ok(None),
+    };
+    let end: u64 = match end {
+        "" => length.saturating_sub(1),
+        end => match end.parse::<u64>() {
+            Ok(end) => end.min(length.saturating_sub(1)),
+            Err(_) => return Ok(None),
+        },
+    };
+    if start >= length {
+        return Err(());
+    }
+    if end < start {
+        return Ok(None);
+    }
+    Ok(Some((start, end)))
+}
+
+fn content_type(path: &Path) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let essence = mime.essence_str();
+    if mime.type_() == mime_guess::mime::TEXT || essence == "application/javascript" || essence == "application/json" {
+        format!("{}; charset=utf-8", essence)
+    } else {
+        essence.to_string()
+    }
+}
+
+//a page linking every entry of the directory, the hidden ones only when they are served
+fn listing(directory: &Path, url_path: &str, dotfiles: bool) -> Option<Response> {
+    let mut entries: Vec<(String, bool)> = fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| dotfiles || !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| {
+            let is_dir = entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false);
+            (entry.file_name().to_string_lossy().to_string(), is_dir)
+        })
+        .collect();
+    entries.sort();
+
+    let title = escape_html(url_path);
+    let mut html = format!("<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{0}</title></head><body><h1>{0}</h1><ul>", title);
+    for (name, is_dir) in entries {
+        let slash = if is_dir { "/" } else { "" };
+        html.push_str(&format!(
+            "<li><a href=\"{}{}\">{}{}</a></li>",
+            utf8_percent_encode(&name, NON_ALPHANUMERIC),
+            slash,
+            escape_html(&name),
+            slash
+        ));
+    }
+    html.push_str("</ul></body></html>");
+    Some(Response::new(200, "text/html; charset=utf-8", html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StaticMount;
+    use crate::request::RawRequest;
+
+    //a project with a `static` directory, and a secret next to it
+    fn project() -> tempfile::TempDir {
+        let project = tempfile::tempdir().unwrap();
+        let root = project.path().join("static");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join("app.css"), "body {}").unwrap();
+        fs::write(root.join("docs/guide.txt"), "guide").unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        fs::write(root.join(".git/config"), "[core]").unwrap();
+        fs::write(project.path().join("secret.txt"), "secret").unwrap();
+        project
+    }
+
+    fn assets(project: &tempfile::TempDir, listing: bool, dotfiles: bool) -> Assets {
+        let config = Config {
+            project_path: project.path().to_string_lossy().to_string(),
+            static_mounts: vec![StaticMount { listing, dotfiles, ..StaticMount::default() }],
+            ..Config::default()
+        };
+        Assets::from_config(&config)
+    }
+
+    fn request(head: &str) -> Request {
+        Request::parse(RawRequest {
+            bytes: head.as_bytes().to_vec(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn get(assets: &Assets, target: &str) -> Option<Response> {
+        let request = request(&format!("GET {} HTTP/1.1\r\nHost: x\r\n\r\n", target));
+        assets.serve(&request, &Conditions::from_request(&request))
+    }
+
+    fn code(assets: &Assets, target: &str) -> i32 {
+        get(assets, target).map(|response| response.code).unwrap_or(0)
+    }
+
+    fn text(response: Response) -> String {
+        match response.body {
+            Body::Bytes(bytes) => String::from_utf8(bytes).unwrap(),
+            _ => panic!("the body is not in memory"),
+        }
+    }
+
+    #[test]
+    fn serves_the_files_of_the_mount() {
+        let project = project();
+        let assets = assets(&project, false, false);
+        let response = get(&assets, "/static/app.css").unwrap();
+        assert_eq!(response.code, 200);
+        assert_eq!(response.header("Content-Type"), Some("text/css; charset=utf-8"));
+        assert_eq!(code(&assets, "/static/docs/guide.txt"), 200);
+        assert_eq!(code(&assets, "/static/missing.css"), 404);
+        assert_eq!(code(&assets, "/static/docs"), 301);
+        assert!(get(&assets, "/staticfiles/app.css").is_none());
+        assert!(get(&assets, "/hello").is_none());
+    }
+
+    #[test]
+    fn refuses_paths_leading_out_of_the_mount() {
+        let project = project();
+        let assets = assets(&project, false, false);
+        assert_eq!(code(&assets, "/static/../secret.txt"), 404);
+        assert_eq!(code(&assets, "/static/docs/../../secret.txt"), 404);
+        assert_eq!(code(&assets, "/static/%2e%2e/secret.txt"), 404);
+        assert_eq!(code(&assets, "/static/%2E%2E%2Fsecret.txt"), 404);
+        assert_eq!(code(&assets, "/static/..%5csecret.txt"), 404);
+        assert_eq!(code(&assets, "/static/app.css%00.txt"), 404);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symbolic_links_leading_out_of_the_mount() {
+        let project = project();
+        std::os::unix::fs::symlink(project.path().join("secret.txt"), project.path().join("static/link.txt")).unwrap();
+        let assets = assets(&project, false, false);
+        assert_eq!(code(&assets, "/static/link.txt"), 404);
+    }
+
+    #[test]
+    fn hides_the_dotfiles() {
+        let project = project();
+        let assets = assets(&project, true, false);
+        assert_eq!(code(&assets, "/static/.env"), 404);
+        assert_eq!(code(&assets, "/static/%2eenv"), 404);
+        assert_eq!(code(&assets, "/static/.git/config"), 404);
+        let listing = text(get(&assets, "/static/").unwrap());
+        assert!(listing.contains("app.css"));
+        assert!(listing.contains("docs/"));
+        assert!(!listing.contains(".env"));
+        assert!(!listing.contains(".git"));
+    }
+
+    #[test]
+    fn serves_the_dotfiles_when_the_mount_allows_them() {
+        let project = project();
+        let assets = assets(&project, true, true);
+        assert_eq!(code(&assets, "/static/.env"), 200);
+        assert_eq!(code(&assets, "/static/.git/config"), 200);
+        let listing = text(get(&assets, "/static/").unwrap());
+        assert!(listing.contains(".env"));
+        assert!(listing.contains(".git/"));
+    }
+
+    #[test]
+    fn lists_only_when_enabled() {
+        let project = project();
+        assert_eq!(code(&assets(&project, false, false), "/static/"), 404);
+        assert_eq!(code(&assets(&project, true, false), "/static/"), 200);
+    }
+
+    #[test]
+    fn parses_a_range() {
+        assert_eq!(parse_range("bytes=0-9", 100), Ok(Some((0, 9))));
+        assert_eq!(parse_range("bytes=90-", 100), Ok(Some((90, 99))));
+        assert_eq!(parse_range("bytes=90-200", 100), Ok(Some((90, 99))));
+        assert_eq!(parse_range("bytes=99-99", 100), Ok(Some((99, 99))));
+        assert_eq!(parse_range(" Bytes = 5 - 6 ", 100), Ok(Some((5, 6))));
+    }
+
+    #[test]
+    fn parses_a_suffix_range() {
+        assert_eq!(parse_range("bytes=-10", 100), Ok(Some((90, 99))));
+        assert_eq!(parse_range("bytes=-200", 100), Ok(Some((0, 99))));
+        assert_eq!(parse_range("bytes=-0", 100), Err(()));
+        assert_eq!(parse_range("bytes=-10", 0), Err(()));
+    }
+
+    #[test]
+    fn refuses_a_range_outside_of_the_file() {
+        assert_eq!(parse_range("bytes=100-", 100), Err(()));
+        assert_eq!(parse_range("bytes=100-200", 100), Err(()));
+        assert_eq!(parse_range("bytes=0-0", 0), Err(()));
+    }
+
+    #[test]
+    fn ignores_the_ranges_it_does_not_support() {
+        assert_eq!(parse_range("bytes=0-9,20-29", 100), Ok(None));
+        assert_eq!(parse_range("items=0-9", 100), Ok(None));
+        assert_eq!(parse_range("bytes=9-0", 100), Ok(None));
+        assert_eq!(parse_range("bytes=a-b", 100), Ok(None));
+        assert_eq!(parse_range("bytes=5", 100), Ok(None));
+        assert_eq!(parse_range("0-9", 100), Ok(None));
+    }
+
+    #[test]
+    fn answers_a_range_request() {
+        let project = project();
+        let assets = assets(&project, false, false);
+        let ranged = request("GET /static/app.css HTTP/1.1\r\nHost: x\r\nRange: bytes=0-3\r\n\r\n");
+        let response = assets.serve(&ranged, &Conditions::from_request(&ranged)).unwrap();
+        assert_eq!(response.code, 206);
+        assert_eq!(response.header("Content-Range"), Some("bytes 0-3/7"));
+        assert_eq!(response.body.length(), Some(4));
+
+        let ranged = request("GET /static/app.css HTTP/1.1\r\nHost: x\r\nRange: bytes=7-\r\n\r\n");
+        let response = assets.serve(&ranged, &Conditions::from_request(&ranged)).unwrap();
+        assert_eq!(response.code, 416);
+        assert_eq!(response.header("Content-Range"), Some("bytes */7"));
+
+        let ranged = request("GET /static/app.css HTTP/1.1\r\nHost: x\r\nRange: bytes=0-3\r\nIf-Range: \"old\"\r\n\r\n");
+        let response = assets.serve(&ranged, &Conditions::from_request(&ranged)).unwrap();
+        assert_eq!(response.code, 200);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::ratelimit::RateLimit;
+use crate::request::TrustedProxies;
+use serde::Deserialize;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+//how each worker thread runs its python handlers
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkerMode {
+    //every worker shares the main interpreter (and its gil)
+    Thread,
+    //every worker owns a sub interpreter, isolated from the others but sharing the gil
+    Subinterpreter,
+}
+
+impl WorkerMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "thread" => Some(WorkerMode::Thread),
+            "subinterpreter" => Some(WorkerMode::Subinterpreter),
+            _ => None,
+        }
+    }
+}
+
+//a directory served as it is under an url prefix, without going through python
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct StaticMount {
+    //the url prefix, like `/static`
+    pub url: String,
+    //the directory, relative to the project
+    pub directory: String,
+    //the files served for a directory, the first one found wins
+    pub index: Vec<String>,
+    //list the content of the directories without an index file
+    pub listing: bool,
+    //serve the files and directories whose name starts with a dot, like `.well-known`
+    pub dotfiles: bool,
+}
+
+impl Default for StaticMount {
+    fn default() -> Self {
+        Self {
+            url: "/static".to_string(),
+            directory: "static".to_string(),
+            index: vec!["index.html".to_string()],
+            listing: false,
+            dotfiles: false,
+        }
+    }
+}
+
+//a certificate chain and its private key, in pem files relative to the project
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TlsCertificate {
+    pub cert: String,
+    pub key: String,
+    //the names it is sent for, like `example.com` or `*.example.com`. the first certificate
+    //is also sent to the clients asking for another name or for none
+    pub server_names: Vec<String>,
+}
+
+//whether the clients must present a certificate signed by `client_ca`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuth {
+    #[default]
+    Required,
+    //the clients without a certificate are accepted too, the handlers can tell them apart
+    Optional,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub certificates: Vec<TlsCertificate>,
+    //the pem file of the authorities signing the client certificates, it enables mutual tls
+    pub client_ca: Option<String>,
+    pub client_auth: ClientAuth,
+}
+
+//the requests a client can make: up to `requests` at once, and `requests` more every `period` seconds
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub requests: u64,
+    pub period: f64,
+    //`ip`, `route` or `header:<name>`, like `header:X-API-Key`
+    pub key: String,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests: 100,
+            period: 60.0,
+            key: "ip".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub project_path: String,
+    pub address: String,
+    pub workers: usize,
+    pub worker_mode: WorkerMode,
+    //restart the workers every time a python file of the project changes
+    pub reload: bool,
+    //render the tracebacks of the handlers in the error pages, never enable it in production
+    pub debug: bool,
+    //the biggest body accepted, in bytes
+    pub max_body_size: usize,
+    //the biggest `multipart/form-data` body accepted, in bytes
+    pub max_upload_size: usize,
+    //bigger multipart bodies and uploaded files are written to temporary files
+    pub upload_memory_size: usize,
+    //signs and encrypts the cookies, at least 32 bytes. it can also be given in `FASTRY_SECRET_KEY`
+    pub secret_key: Option<String>,
+    //the cidrs of the reverse proxies whose `Forwarded` and `X-Forwarded-*` headers are honored
+    pub trusted_proxies: Vec<String>,
+    //the directories served by the server itself
+    pub static_mounts: Vec<StaticMount>,
+    //compress the responses with the coding the client prefers
+    pub compression: bool,
+    //smaller bodies are sent as they are, streams are always compressed
+    pub compression_min_size: usize,
+    //the content types compressed: `text/` matches every text type and `+json` every json based type
+    pub compression_types: Vec<String>,
+    //speak http/2 with the clients negotiating it with alpn, and with the h2c ones
+    pub http2: bool,
+    //serve https instead of http, the certificates are loaded again on SIGHUP
+    pub tls: Option<TlsConfig>,
+    //the biggest websocket message accepted, in bytes. the connection is closed with 1009 above it
+    pub websocket_max_message_size: usize,
+    //seconds between the pings sent to the websocket clients, the ones not answering are dropped
+    pub websocket_ping_interval: u64,
+    //seconds an event stream can stay silent before a keep-alive comment is sent
+    pub sse_keep_alive_interval: u64,
+    //seconds a handler has to return its response before the client gets a 504, routes can
+    //change it with their `timeout=` flag
+    pub handler_timeout: Option<f64>,
+    //seconds a client has to send the head of its request
+    pub header_read_timeout: u64,
+    //seconds the body of a request can stay without receiving anything
+    pub body_read_timeout: u64,
+    //seconds a response can stay without the client taking anything
+    pub write_timeout: u64,
+    //seconds a connection is kept open without requests
+    pub idle_timeout: u64,
+    //the connections served at once, the next ones wait in the backlog
+    pub max_connections: usize,
+    //the connections a single address can have open at once, behind a proxy it is the proxy address
+    pub max_connections_per_ip: Option<usize>,
+    //the connections the system keeps waiting to be accepted
+    pub listen_backlog: i32,
+    //the requests waiting for a worker, the next ones are answered with a 503
+    pub queue_size: usize,
+    //seconds a request can wait for a worker, the older ones are answered with a 503
+    pub queue_timeout: Option<f64>,
+    //seconds the clients are told to wait before retrying a 503
+    pub retry_after: u64,
+    //the limit of every route, the routes can have their own with a `rate=` flag
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            project_path: env::var("FASTRY_PROJECT_PATH").unwrap_or_else(|_| {
+                env::current_dir()
+                    .map(|dir| dir.to_string_lossy().to_string())
+                    .unwrap_or_else(|_| ".".to_string())
+            }),
+            address: "127.0.0.1:8080".to_string(),
+            workers: 10,
+            worker_mode: WorkerMode::Thread,
+            reload: false,
+            debug: false,
+            max_body_size: 10 * 1024 * 1024,
+            max_upload_size: 100 * 1024 * 1024,
+            upload_memory_size: 1024 * 1024,
+            secret_key: env::var("FASTRY_SECRET_KEY").ok(),
+            trusted_proxies: Vec::new(),
+            static_mounts: Vec::new(),
+            compression: true,
+            compression_min_size: 1024,
+            compression_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "image/svg+xml",
+                "+json",
+                "+xml",
+            ]
+            .iter()
+            .map(|content_type| content_type.to_string())
+            .collect(),
+            http2: true,
+            tls: None,
+            websocket_max_message_size: 1024 * 1024,
+            websocket_ping_interval: 30,
+            sse_keep_alive_interval: 15,
+            handler_timeout: None,
+            header_read_timeout: 10,
+            body_read_timeout: 30,
+            write_timeout: 30,
+            idle_timeout: 60,
+            max_connections: 10000,
+            max_connections_per_ip: None,
+            listen_backlog: 1024,
+            queue_size: 1024,
+            queue_timeout: None,
+            retry_after: 1,
+            rate_limit: None,
+        }
+    }
+}
+
+impl Config {
+    //builds the configuration from the run arguments, the project `fastry.json` file
+    //is read first, and every argument given in the command line overrides it
+    pub fn load() -> Self {
+        let args: Vec<String> = env::args().skip(1).collect();
+
+        //the project path is needed before anything else, to find the config file
+        let mut project_path = Self::default().project_path;
+        if let Some(idx) = args.iter().position(|arg| arg == "--project") {
+            if let Some(path) = args.get(idx + 1) {
+                project_path = path.to_string();
+            }
+        }
+
+        let mut config = Self::from_file(&project_path).unwrap_or_default();
+        config.project_path = project_path;
+        config.apply_args(&args);
+        if config.secret_key.as_ref().map(|key| key.len() < 32).unwrap_or(false) {
+            panic!("The secret_key must be at least 32 bytes long");
+        }
+        if config.handler_timeout.map(|timeout| !(timeout > 0.0 && timeout.is_finite())).unwrap_or(false) {
+            panic!("The handler_timeout must be a positive number of seconds");
+        }
+        if config.queue_timeout.map(|timeout| !(timeout > 0.0 && timeout.is_finite())).unwrap_or(false) {
+            panic!("The queue_timeout must be a positive number of seconds");
+        }
+        if config.queue_size == 0 {
+            panic!("The queue_size must be at least 1");
+        }
+        if config.max_connections == 0 || config.max_connections_per_ip == Some(0) {
+            panic!("The max_connections and max_connections_per_ip must be at least 1");
+        }
+        if config.listen_backlog <= 0 {
+            panic!("The listen_backlog must be at least 1");
+        }
+        if let Some(Err(err)) = config.rate_limit.as_ref().map(RateLimit::from_config) {
+            panic!("Invalid rate_limit: {}", err);
+        }
+        if let Err(err) = TrustedProxies::parse(&config.trusted_proxies) {
+            panic!("Invalid trusted_proxies: {}", err);
+        }
+        config
+    }
+
+    fn from_file(project_path: &str) -> Option<Self> {
+        let file_name = Path::new(project_path).join("fastry.json");
+        let mut file = File::open(&file_name).ok()?;
+        let mut raw = String::new();
+        file.read_to_string(&mut raw).ok()?;
+        match serde_json::from_str(raw.as_str()) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                panic!("Invalid config file {}: {}", file_name.display(), err);
+            }
+        }
+    }
+
+    fn apply_args(&mut self, args: &[String]) {
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "serve" => {
+                    //the only command for now, serving is also the default
+                }
+                "--reload" => {
+                    self.reload = true;
+                }
+                "--debug" => {
+                    self.debug = true;
+                }
+                "--project" => {
+                    //already handled while loading the config file
+                    args.next();
+                }
+                "--workers" => {
+                    let value = args.next().expect("--workers needs a value");
+                    self.workers = value.parse().expect("--workers must be a number");
+                }
+                "--worker-mode" => {
+                    let value = args.next().expect("--worker-mode needs a value");
+                    self.worker_mode = WorkerMode::from_name(value)
+                        .expect("--worker-mode must be `thread` or `subinterpreter`");
+                }
+                address => {
+                    //kept for backwards compatibility, the address used to be the only argument
+                    self.address = address.to_string();
+                }
+            }
+        }
+    }
+}
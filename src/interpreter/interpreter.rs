@@ -0,0 +1,278 @@
+extern crate libc;
+extern crate pyo3;
+
+use crate::config::WorkerMode;
+use pyo3::ffi;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_long};
+use std::ptr;
+
+//mirrors `PyInterpreterConfig`, only available on python 3.12+
+#[repr(C)]
+struct PyInterpreterConfig {
+    use_main_obmalloc: c_int,
+    allow_fork: c_int,
+    allow_exec: c_int,
+    allow_threads: c_int,
+    allow_daemon_threads: c_int,
+    check_multi_interp_extensions: c_int,
+    gil: c_int,
+}
+
+const PY_INTERPRETER_CONFIG_SHARED_GIL: c_int = 1;
+
+//mirrors `PyStatus`, returned by the python initialization functions
+#[repr(C)]
+struct PyStatus {
+    _type: c_int,
+    func: *const c_char,
+    err_msg: *const c_char,
+    exitcode: c_int,
+}
+
+type NewInterpreterFromConfig =
+    unsafe extern "C" fn(*mut *mut ffi::PyThreadState, *const PyInterpreterConfig) -> PyStatus;
+
+//the python interpreter a worker executes its handlers in
+pub enum Interpreter {
+    //the main interpreter, shared by every worker through the gil
+    Main,
+    //a sub interpreter owned by a single worker thread, sharing the gil of the main one
+    Sub(SubInterpreter),
+}
+
+pub struct SubInterpreter {
+    tstate: *mut ffi::PyThreadState,
+    interp: *mut ffi::PyInterpreterState,
+    //the thread state of the main interpreter that was current when this one was created
+    main_tstate: *mut ffi::PyThreadState,
+    gstate: ffi::PyGILState_STATE,
+}
+
+impl Interpreter {
+    pub fn new(mode: WorkerMode, project_path: &str) -> Self {
+        match mode {
+            WorkerMode::Thread => Interpreter::Main,
+            WorkerMode::Subinterpreter => match SubInterpreter::new(project_path) {
+                Ok(sub) => Interpreter::Sub(sub),
+                Err(reason) => {
+                    println!("Could not create a sub interpreter ({}), using the main interpreter", reason);
+                    Interpreter::Main
+                }
+            },
+        }
+    }
+
+    pub fn is_sub(&self) -> bool {
+        matches!(self, Interpreter::Sub(_))
+    }
+
+    //runs the closure holding the gil of this interpreter
+    pub fn with_gil<F, R>(&self, f: F) -> R
+    where
+        F: for<'py> FnOnce(Python<'py>) -> R,
+    {
+        match self {
+            Interpreter::Main => unsafe { Python::with_gil_unchecked(f) },
+            Interpreter::Sub(sub) => unsafe {
+                ffi::PyEval_RestoreThread(sub.tstate);
+                //the pool releases every python reference created inside the closure
+                let pool = Python::assume_gil_acquired().new_pool();
+                let result = f(pool.python());
+                drop(pool);
+                ffi::PyEval_SaveThread();
+                result
+            },
+        }
+    }
+}
+
+//raises an exception in the thread running a handler, from another thread
+#[derive(Clone, Copy)]
+pub struct Interrupter {
+    //None for the main interpreter
+    interp: Option<*mut ffi::PyInterpreterState>,
+    thread_id: c_long,
+}
+
+//the interpreter outlives the handlers it is used for, the worker stops them first
+unsafe impl Send for Interrupter {}
+
+impl Interpreter {
+    //interrupts the handlers this thread runs in this interpreter
+    pub fn interrupter(&self) -> PyResult<Interrupter> {
+        let thread_id = self.with_gil(|py| py.import("threading")?.call_method0("get_ident")?.extract::<u64>())?;
+        Ok(Interrupter {
+            interp: match self {
+                Interpreter::Main => None,
+                Interpreter::Sub(sub) => Some(sub.interp),
+            },
+            thread_id: thread_id as c_long,
+        })
+    }
+}
+
+impl Interrupter {
+    //raises a TimeoutError in the thread the next time it runs python code, a thread blocked
+    //in a call sees it once the call returns
+    pub fn raise_timeout(&self) {
+        unsafe {
+            match self.interp {
+                None => Python::with_gil(|_| {
+                    ffi::PyThreadState_SetAsyncExc(self.thread_id, ffi::PyExc_TimeoutError);
+                }),
+                Some(interp) => {
+                    //a thread state of its own in the interpreter, to take its gil
+                    let tstate = ffi::PyThreadState_New(interp);
+                    ffi::PyEval_RestoreThread(tstate);
+                    ffi::PyThreadState_SetAsyncExc(self.thread_id, ffi::PyExc_TimeoutError);
+                    ffi::PyThreadState_Clear(tstate);
+                    ffi::PyEval_SaveThread();
+                    ffi::PyThreadState_Delete(tstate);
+                }
+            }
+        }
+    }
+
+    //forgets the exception not raised yet, the handler returned before running more python code.
+    //it has to be called from the interrupted thread
+    pub fn clear(&self, _py: Python) {
+        unsafe {
+            ffi::PyThreadState_SetAsyncExc(self.thread_id, ptr::null_mut());
+        }
+    }
+}
+
+impl SubInterpreter {
+    fn new(project_path: &str) -> Result<Self, String> {
+        unsafe {
+            //a thread state of the main interpreter has to be current to create a new one
+            let gstate = ffi::PyGILState_Ensure();
+            let main_tstate = ffi::PyThreadState_Get();
+            let mut tstate: *mut ffi::PyThreadState = ptr::null_mut();
+
+            //the gil is shared with the main interpreter: the types pyo3 creates for the classes
+            //given to the handlers are static, they live in the first interpreter using them and
+            //are not safe to touch from interpreters with a gil of their own
+            match new_interpreter_from_config() {
+                Some(new_interpreter) => {
+                    //python 3.12+, the extension modules are checked like for an isolated interpreter
+                    let config = PyInterpreterConfig {
+                        use_main_obmalloc: 1,
+                        allow_fork: 0,
+                        allow_exec: 0,
+                        allow_threads: 1,
+                        allow_daemon_threads: 0,
+                        //refuse extension modules that are not safe to load in several interpreters
+                        check_multi_interp_extensions: 1,
+                        gil: PY_INTERPRETER_CONFIG_SHARED_GIL,
+                    };
+                    let status = new_interpreter(&mut tstate, &config);
+                    if status._type != 0 {
+                        ffi::PyGILState_Release(gstate);
+                        let reason = if status.err_msg.is_null() {
+                            "unknown error".to_string()
+                        } else {
+                            CStr::from_ptr(status.err_msg).to_string_lossy().to_string()
+                        };
+                        return Err(reason);
+                    }
+                }
+                None => {
+                    //older pythons, the extension modules are not checked until they are imported
+                    tstate = ffi::Py_NewInterpreter();
+                    if tstate.is_null() {
+                        ffi::PyGILState_Release(gstate);
+                        return Err("Py_NewInterpreter failed".to_string());
+                    }
+                }
+            }
+
+            //the new interpreter is the current one, and every interpreter has its own sys.path
+            let pool = Python::assume_gil_acquired().new_pool();
+            let interp = ffi::PyInterpreterState_Get();
+            let configured = configure_sys_path(pool.python(), project_path);
+            if let Err(err) = &configured {
+                err.print(pool.python());
+            }
+            drop(pool);
+            ffi::PyEval_SaveThread();
+
+            let sub = Self {
+                tstate,
+                interp,
+                main_tstate,
+                gstate,
+            };
+            match configured {
+                Ok(_) => Ok(sub),
+                Err(_) => Err("could not configure sys.path".to_string()),
+            }
+        }
+    }
+}
+
+impl Drop for SubInterpreter {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::PyEval_RestoreThread(self.tstate);
+            ffi::Py_EndInterpreter(self.tstate);
+            //no thread state is current now but the shared gil is still held,
+            //go back to the main one to release it
+            ffi::PyThreadState_Swap(self.main_tstate);
+            ffi::PyGILState_Release(self.gstate);
+        }
+    }
+}
+
+//looks up `Py_NewInterpreterFromConfig` in the linked libpython, it only exists on python 3.12+
+fn new_interpreter_from_config() -> Option<NewInterpreterFromConfig> {
+    unsafe {
+        let symbol = libc::dlsym(
+            libc::RTLD_DEFAULT,
+            c"Py_NewInterpreterFromConfig".as_ptr(),
+        );
+        if symbol.is_null() {
+            None
+        } else {
+            Some(std::mem::transmute::<*mut libc::c_void, NewInterpreterFromConfig>(symbol))
+        }
+    }
+}
+
+//makes the project and its venv importable from the given interpreter
+pub fn configure_sys_path(py: Python, path: &str) -> PyResult<()> {
+    let syspath: &PyList = py.import("sys")?.getattr("path")?.extract()?;
+
+    syspath.append(path)?;
+
+    //add the venv to the syspath, its packages are built for the python running them
+    let version_info = py.import("sys")?.getattr("version_info")?;
+    let (major, minor): (u8, u8) = (version_info.getattr("major")?.extract()?, version_info.getattr("minor")?.extract()?);
+    let venv_path = format!("{}/venv/lib/python{}.{}/site-packages/", path, major, minor);
+    syspath.append(venv_path)?;
+    Ok(())
+}
+
+//removes the modules of the project from sys.modules, so they are executed again on the next import
+pub fn unload_project_modules(py: Python, path: &str) -> PyResult<()> {
+    let modules: &PyDict = py.import("sys")?.getattr("modules")?.downcast()?;
+    let project_root = std::path::Path::new(path);
+    let mut project_modules = Vec::new();
+    for (name, module) in modules.iter() {
+        let file = match module.getattr("__file__") {
+            Ok(file) if !file.is_none() => file.extract::<String>()?,
+            _ => continue,
+        };
+        let file = std::path::Path::new(&file);
+        if file.starts_with(project_root) && !file.starts_with(project_root.join("venv")) {
+            project_modules.push(name);
+        }
+    }
+    for name in project_modules {
+        modules.del_item(name)?;
+    }
+    Ok(())
+}
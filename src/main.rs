@@ -3,133 +3,143 @@
 extern crate time;
 extern crate tokio;
 extern crate pyo3;
+extern crate socket2;
 use crate::fs::DirEntry;
 use pyo3::prelude::*;
 use pyo3::PyErr;
-use pyo3::types::PyList;
-use std::env;
 use std::fs;
 use std::io;
 use std::net::SocketAddr;
 use std::path::Path;
-use std::sync::mpsc::{Sender, Receiver};
-use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
 
 pub mod app;
+pub mod assets;
+pub mod config;
+pub mod interpreter;
+pub mod ratelimit;
+pub mod reload;
 pub mod request;
+pub mod response;
+pub mod server;
+pub mod tls;
 use app::App;
-use request::Request;
+use config::Config;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::thread;
-use std::io::Read;
-use std::time::Instant;
-use std::net::{TcpListener, TcpStream};
+use std::net::TcpListener;
+use server::{Server, WorkerPool};
+use socket2::{Domain, Socket, Type};
+use tls::Tls;
 
 fn main() {
-    let mut app = App::new();
+    let config = Config::load();
+    let mut app = App::from_config(config.clone());
     //ensure python path is set to the correct value
 
     //get all the routes of the project
-    //TODO: move this to an env variable or to the executable run arguments 
-    let project_path = "/Users/robertoskr/personalthings/website";
+    let project_path = config.project_path.as_str();
 
     prepare_python_things(project_path).unwrap();
 
-    let raw_routes: Vec<(String, String)> = get_routes(project_path);
+    let routes = get_routes(project_path);
     //register all the routes
-    app.register_routes(raw_routes);
+    app.register_routes(routes.found);
 
-    //start the tcp server
-    let addr = env::args().nth(1).unwrap_or("127.0.0.1:8080".to_string());
-    let addr = addr.parse::<SocketAddr>().unwrap();
-    let listener = TcpListener::bind(&addr).unwrap();
+    //refuse to start if any route can not be loaded, instead of failing at request time
+    if !check_routes(&app, &routes.problems) { 
+        std::process::exit(1);
+    } 
 
-    let mut workers = Vec::new();
+    //bound before the workers start, so a busy address is reported right away
+    let addr = config.address.parse::<SocketAddr>().unwrap();
+    let listener = listen(addr, config.listen_backlog).unwrap();
+    let tls = config.tls.as_ref().map(|tls_config| {
+        let tls = Tls::load(tls_config, project_path, config.http2)
+            .unwrap_or_else(|err| panic!("Invalid tls config: {}", err));
+        Arc::new(tls)
+    });
 
-    for _ in 0..10 { 
-        add_and_start_worker(&mut workers, project_path, &app); 
-    } 
+    let pool = Arc::new(Mutex::new(WorkerPool::start(app, config.clone())));
 
-    let mut worker_id = 0;
-    let mut request_counter = 0;
-    let mut start_time = Instant::now();
-    for stream in listener.incoming() {
-        let mut socket = stream.unwrap();
-        //clone the app to pass it to the thread
-        let worker = workers[worker_id].to_owned();
-        
-        //TODO: 16 bytes, we should make this dinamic ofc!
-        let mut buffer = [0; 16384];
-        //read the request data into the buffer
-        let bytes_read = socket.read(&mut buffer).unwrap();
-        let request_str = match std::str::from_utf8(&buffer[..bytes_read]) { 
-            Ok(s) => s, 
-            Err(_) => "",
-        };
-
-        //process the request
-        match worker.send((Some(socket), request_str.to_string())) { 
-            Ok(_) => (), 
-            Err(_) => { 
-                //remove the worker from the worker list 
-                remove_worker_try_stop(&mut workers, worker_id);
+    //in development, the project is reloaded every time one of its files changes
+    if config.reload { 
+        let changes = reload::watch(project_path);
+        let pool = pool.clone();
+        let config = config.clone();
+        thread::spawn(move || { 
+            while changes.recv().is_ok() { 
+                //several changes can be waiting, one reload covers all of them
+                changes.try_iter().count();
+                //the project is loaded again before taking the pool, the requests keep going to the old workers meanwhile
+                let app = WorkerPool::load_app(&config);
+                pool.lock().unwrap().reload(app);
             } 
-        }
+        });
+    } 
 
-        //now lets see if we should add remove workers based on the traffic of the application
-        let now = Instant::now();
-        if now.duration_since(start_time).as_secs() > 60 { 
-            let n_workers = workers.len();
-            let ratio = request_counter as f64 / 60.0 / n_workers as f64;
-            if ratio > 5.0 { 
-                //add more workers 
-                add_and_start_worker(&mut workers, project_path, &app); 
-            } else if ratio < 0.2 { 
-                //remove some workers
-                remove_worker_try_stop(&mut workers, n_workers - 1);
-            }
+    //the connections are handled by tokio, the handlers by the worker threads
+    let server = Arc::new(Server::new(pool, tls, &config));
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    runtime.block_on(server.run(listener));
+} 
 
-            start_time = now;
-            request_counter = 0;
-        } 
+//binds the address, with room in the backlog for the connections waiting to be accepted
+fn listen(addr: SocketAddr, backlog: i32) -> io::Result<TcpListener> { 
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    //a restarted server can bind while the connections of the previous one are closing
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    Ok(socket.into())
+} 
 
-        //advance the worker !
-        worker_id = worker_id + 1;
-        if worker_id >= workers.len() { 
-            worker_id = 0; 
-        } 
-        request_counter += 1;
+//loads the handler of every route in the main interpreter, printing the ones that can not be
+//loaded together with the declarations that did not make a route
+fn check_routes(app: &App, problems: &[String]) -> bool { 
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    //the handlers are loaded in a copy, the workers load their own in their interpreter
+    let mut checked_app = app.clone();
+    let failures = checked_app.load_handlers(py);
+    if failures.is_empty() && problems.is_empty() { 
+        return true;
     } 
-}
-
-
-fn remove_worker_try_stop(workers: &mut Vec<Sender<(Option<TcpStream>, String)>>, idx: usize) { 
-    let worker = workers.remove(idx);
-    //TODO: we should send a signal to the tread an kill it
-    worker.send((None, String::new())).unwrap();
+    println!("Could not load {} routes:", failures.len() + problems.len());
+    for problem in problems { 
+        println!("  {}", problem);
+    } 
+    for (route, handler, err) in failures { 
+        println!("  {} -> {}: {}", route, handler, err);
+    } 
+    false
 } 
 
-fn add_and_start_worker(workers: &mut Vec<Sender<(Option<TcpStream>, String)>>, project_path: &str, application: &App) { 
-    let mut worker = application.clone();
-    let (tx, rx): (Sender<(Option<TcpStream>, String)>, Receiver<(Option<TcpStream>, String)>) = mpsc::channel();
-    let clone = project_path.to_owned();
-    thread::spawn(move || {
-        worker.start(&clone, rx); 
-    });
-    workers.push(tx);
+//the routes declared in the project, and why the declarations that are not a route were left out
+#[derive(Default)]
+pub struct Routes { 
+    pub found: Vec<(String, String)>,
+    pub problems: Vec<String>,
 } 
 
-fn visit_dirs(
+fn visit_dirs<T>(
     dir: &Path,
-    cb: &dyn Fn(&DirEntry, &mut Vec<(String, String)>),
-    container: &mut Vec<(String, String)>,
+    cb: &dyn Fn(&DirEntry, &mut T),
+    container: &mut T,
 ) -> io::Result<()> {
     if dir.is_dir() {
         for entry in fs::read_dir(dir)? {
-            let entry = entry?;
+            //the files removed while walking the project are skipped
+            let entry = match entry { 
+                Ok(entry) => entry, 
+                Err(_) => continue, 
+            };
             let path = entry.path();
-            let path_str = path.to_str().unwrap();
+            let path_str = match path.to_str() { 
+                Some(path_str) => path_str, 
+                None => continue, 
+            };
             if path_str.find("venv").is_some() || path_str.find("target").is_some() {
                 continue;
             }
@@ -143,52 +153,93 @@ fn visit_dirs(
     Ok(())
 }
 
-fn visit_python_file(entry: &DirEntry, container: &mut Vec<(String, String)>) {
+fn visit_python_file(entry: &DirEntry, routes: &mut Routes) {
     let path = entry.path();
-    let path = path.to_str().unwrap();
-    if path.find(".py").is_none() {
-        return;
-    }
+    let path = match path.to_str() { 
+        Some(path) if path.ends_with(".py") => path, 
+        _ => return, 
+    };
 
-    let routes = get_routes_for_file(path);
-    for (path, fn_name) in routes {
-        container.push((path, fn_name));
+    //the file can be gone, or half written while it is being edited
+    if let Err(err) = get_routes_for_file(path, routes) { 
+        routes.problems.push(format!("{}: the routes can not be read: {}", path, err));
     }
 }
 
-fn get_routes(project_path: &str) -> Vec<(String, String)> {
-    let mut container: Vec<(String, String)> = Vec::new();
-    _ = visit_dirs(Path::new(project_path), &visit_python_file, &mut container);
+fn get_routes(project_path: &str) -> Routes {
+    let mut routes = Routes::default();
+    _ = visit_dirs(Path::new(project_path), &visit_python_file, &mut routes);
 
-    container
+    //handlers are imported by module name, so every module is executed once per interpreter
+    let mut found = Vec::new();
+    for (route, handler) in routes.found { 
+        let (file_name, fn_name) = handler.split_once("::").unwrap();
+        match module_name(project_path, file_name) { 
+            Some(module) => found.push((route, format!("{}::{}", module, fn_name))), 
+            None => routes.problems.push(format!("{} -> {}: {} is not an importable module", route, handler, file_name)), 
+        }
+    }
+    routes.found = found;
+    routes
+}
+
+//converts the path of a python file of the project to its dotted module name
+fn module_name(project_path: &str, file_name: &str) -> Option<String> {
+    let relative = Path::new(file_name).strip_prefix(project_path).ok()?.with_extension("");
+    let mut parts: Vec<String> = Vec::new();
+    for part in relative.iter() {
+        let part = part.to_str()?;
+        if part.is_empty() || part.contains('.') || part.contains('-') {
+            return None;
+        }
+        parts.push(part.to_string());
+    }
+    //a package handler lives in its `__init__.py`
+    if parts.len() > 1 && parts.last().unwrap() == "__init__" {
+        parts.pop();
+    }
+    Some(parts.join("."))
 }
 
-pub fn get_routes_for_file(path: &str) -> Vec<(String, String)> {
+//adds the routes declared in the file, the malformed ones are added to the problems
+pub fn get_routes_for_file(path: &str, routes: &mut Routes) -> io::Result<()> {
     //get all the routes from the app base file
-    let file = File::open(path).unwrap();
+    let file = File::open(path)?;
     let reader = BufReader::new(file);
     let mut last_path: Option<String> = None;
-    let mut routes: Vec<(String, String)> = Vec::new();
-    for line in reader.lines() {
+    for (idx, line) in reader.lines().enumerate() {
         let line = line.unwrap_or("".to_string());
         if line.find("#->r").is_some() {
             //this is a route function
-            let route_start = line.find('/').unwrap();
-            last_path = Some(line[route_start..].trim().to_string());
+            last_path = match line.find('/') { 
+                Some(route_start) => Some(line[route_start..].trim().to_string()), 
+                None => { 
+                    routes.problems.push(format!("{}:{}: the route has no path", path, idx + 1));
+                    None
+                } 
+            };
+            continue;
+        }
+        if line.trim_start().starts_with("#->") { 
+            routes.problems.push(format!("{}:{}: `{}` is not a route marker, routes are declared with `#->r`", path, idx + 1, line.trim()));
             continue;
         }
         if line.trim() != "" && last_path.is_some() {
             //get the name of the function
-            let (_, name) = line.split_once("def").unwrap();
-            routes.push((
-                last_path.unwrap(),
-                format!("{}::{}", path, name.split_once('(').unwrap().0.trim()).to_string(),
-            ));
-            last_path = None;
+            let route = last_path.take().unwrap();
+            let name = line
+                .split_once("def")
+                .and_then(|(_, rest)| rest.split_once('('))
+                .map(|(name, _)| name.trim())
+                .filter(|name| !name.is_empty());
+            match name { 
+                Some(name) => routes.found.push((route, format!("{}::{}", path, name))), 
+                None => routes.problems.push(format!("{}:{}: the route {} is not followed by a function", path, idx + 1, route)), 
+            }
             continue;
         }
     }
-    routes
+    Ok(())
 }
 
 fn prepare_python_things(path: &str) -> Result<(), PyErr> { 
@@ -203,17 +254,7 @@ fn prepare_python_things(path: &str) -> Result<(), PyErr> {
         let os = python.import("os")?;
         os.call_method1("chdir", (path, ))?;
 
-        let syspath: &PyList = python
-            .import("sys")?
-            .getattr("path")?
-            .extract()?;
-
-        syspath.append(path)?;
-
-        //add the venv to the syspath 
-        let mut venv_path = path.to_string();
-        venv_path.push_str("/venv/lib/python3.10/site-packages/");
-        syspath.append(venv_path)?;
+        interpreter::configure_sys_path(python, path)?;
     }
     Ok(()) 
 } 
@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+//what a bucket answered to a request
+#[derive(Debug, Clone, Copy)]
+pub struct Decision {
+    pub allowed: bool,
+    //the requests left in the bucket
+    pub remaining: u64,
+    //until the bucket is full again
+    pub reset: Duration,
+    //until the next request is allowed, zero when it already is
+    pub retry_after: Duration,
+}
+
+//where the token buckets live. the one in memory only counts the requests of this process, a
+//shared store lets several servers count the same clients
+pub trait RateLimitBackend: Send + Sync {
+    //takes a token from the bucket of the key. the bucket holds up to `capacity` tokens and gets
+    //them back one by one, `capacity` every `period`, a new one starts full
+    fn take(&self, key: &str, capacity: u64, period: Duration) -> Decision;
+}
@@ -0,0 +1,201 @@
+use crate::ratelimit::{Decision, RateLimitBackend};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+//how often the full buckets are forgotten, a full bucket is the same as a missing one
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+//the most buckets kept at once, so clients making up new keys can not exhaust the memory
+const MAX_BUCKETS: usize = 100_000;
+
+//the buckets in a map shared by the workers of the process
+pub struct MemoryBackend {
+    state: Mutex<State>,
+    max_buckets: usize,
+}
+
+struct State {
+    buckets: HashMap<String, Bucket>,
+    last_sweep: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full_at: Instant,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::with_max_buckets(MAX_BUCKETS)
+    }
+
+    fn with_max_buckets(max_buckets: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+            max_buckets,
+        }
+    }
+
+    fn take_at(&self, key: &str, capacity: u64, period: Duration, now: Instant) -> Decision {
+        let capacity = capacity as f64;
+        //tokens per second
+        let rate = capacity / period.as_secs_f64();
+        let mut state = self.state.lock().unwrap();
+        if now.duration_since(state.last_sweep) >= SWEEP_INTERVAL {
+            state.buckets.retain(|_, bucket| bucket.full_at > now);
+            state.last_sweep = now;
+        }
+        if !state.buckets.contains_key(key) {
+            if state.buckets.len() >= self.max_buckets {
+                state.make_room(self.max_buckets, now);
+            }
+            let bucket = Bucket {
+                tokens: capacity,
+                updated: now,
+                full_at: now,
+            };
+            state.buckets.insert(key.to_string(), bucket);
+        }
+        let bucket = state.buckets.get_mut(key).unwrap();
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(capacity);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let reset = Duration::from_secs_f64((capacity - bucket.tokens) / rate);
+        bucket.full_at = now + reset;
+        let retry_after = match bucket.tokens >= 1.0 {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64((1.0 - bucket.tokens) / rate),
+        };
+        Decision {
+            allowed,
+            remaining: bucket.tokens.floor() as u64,
+            reset,
+            retry_after,
+        }
+    }
+}
+
+impl State {
+    //forgets the full buckets, and when there are still too many the least recently used tenth
+    //of them at once, so the next new clients do not pay for it again
+    fn make_room(&mut self, max_buckets: usize, now: Instant) {
+        self.buckets.retain(|_, bucket| bucket.full_at > now);
+        self.last_sweep = now;
+        if self.buckets.len() < max_buckets {
+            return;
+        }
+        let mut updated: Vec<Instant> = self.buckets.values().map(|bucket| bucket.updated).collect();
+        let evicted = (max_buckets / 10).max(1);
+        let oldest = *updated.select_nth_unstable(evicted - 1).1;
+        self.buckets.retain(|_, bucket| bucket.updated > oldest);
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimitBackend for MemoryBackend {
+    fn take(&self, key: &str, capacity: u64, period: Duration) -> Decision {
+        self.take_at(key, capacity, period, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_secs(3);
+
+    fn after(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn starts_with_a_full_bucket() {
+        let backend = MemoryBackend::new();
+        let decision = backend.take_at("client", 3, PERIOD, Instant::now());
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 2);
+        assert_eq!(decision.retry_after, Duration::ZERO);
+        assert_eq!(decision.reset, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn denies_the_requests_over_the_capacity() {
+        let backend = MemoryBackend::new();
+        let start = Instant::now();
+        for remaining in [2, 1, 0] {
+            let decision = backend.take_at("client", 3, PERIOD, start);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+        let decision = backend.take_at("client", 3, PERIOD, start);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.retry_after, Duration::from_secs(1));
+        assert_eq!(decision.reset, PERIOD);
+        //the other keys have their own buckets
+        assert!(backend.take_at("other", 3, PERIOD, start).allowed);
+    }
+
+    #[test]
+    fn refills_continuously() {
+        let backend = MemoryBackend::new();
+        let start = Instant::now();
+        for _ in 0..3 {
+            backend.take_at("client", 3, PERIOD, start);
+        }
+        //a token every second
+        let decision = backend.take_at("client", 3, PERIOD, after(start, 500));
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, Duration::from_millis(500));
+        let decision = backend.take_at("client", 3, PERIOD, after(start, 1000));
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert!(!backend.take_at("client", 3, PERIOD, after(start, 1000)).allowed);
+        //never above the capacity
+        let decision = backend.take_at("client", 3, PERIOD, after(start, 60_000));
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 2);
+    }
+
+    #[test]
+    fn caps_the_buckets() {
+        let backend = MemoryBackend::with_max_buckets(10);
+        let start = Instant::now();
+        for client in 0..100 {
+            backend.take_at(&format!("client {}", client), 3, PERIOD, after(start, client));
+        }
+        assert!(backend.state.lock().unwrap().buckets.len() <= 10);
+        //the most recent ones are kept
+        assert!(backend.state.lock().unwrap().buckets.contains_key("client 99"));
+        assert!(!backend.state.lock().unwrap().buckets.contains_key("client 0"));
+    }
+
+    #[test]
+    fn forgets_the_full_buckets_before_the_others() {
+        let backend = MemoryBackend::with_max_buckets(10);
+        let start = Instant::now();
+        for _ in 0..3 {
+            backend.take_at("busy", 3, PERIOD, start);
+        }
+        for client in 1..10 {
+            backend.take_at(&format!("client {}", client), 3, Duration::from_millis(30), after(start, client));
+        }
+        //the quiet clients are full again, the busy one still has to wait
+        let decision = backend.take_at("new", 3, PERIOD, after(start, 100));
+        assert!(decision.allowed);
+        assert!(!backend.take_at("busy", 3, PERIOD, after(start, 100)).allowed);
+        assert_eq!(backend.state.lock().unwrap().buckets.len(), 2);
+    }
+}
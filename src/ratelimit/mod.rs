@@ -0,0 +1,8 @@
+pub mod backend;
+pub mod memory;
+#[allow(clippy::module_inception)]
+pub mod ratelimit;
+
+pub use backend::*;
+pub use memory::*;
+pub use ratelimit::*;
@@ -0,0 +1,289 @@
+use crate::config::{Config, RateLimitConfig};
+use crate::ratelimit::{Decision, MemoryBackend, RateLimitBackend};
+use crate::request::{ClientInfo, Headers};
+use crate::response::Response;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+//what the requests are counted by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitKey {
+    //the address of the client, behind trusted proxies the one they forwarded
+    Ip,
+    //the value of a header, like an api key. the requests without it are counted by address.
+    //the value is trusted as it is, a client making up a new one every time is never limited
+    Header(String),
+    //every client of the route together
+    Route,
+}
+
+impl RateLimitKey {
+    pub fn parse(key: &str) -> Result<Self, String> {
+        match key {
+            "ip" => Ok(RateLimitKey::Ip),
+            "route" => Ok(RateLimitKey::Route),
+            key if key.starts_with("header:") && key.len() > "header:".len() => {
+                Ok(RateLimitKey::Header(key["header:".len()..].to_string()))
+            }
+            key => Err(format!("unknown key `{}`, it can be `ip`, `route` or `header:<name>`", key)),
+        }
+    }
+}
+
+//a token bucket of `requests` tokens, refilled continuously at `requests` every `period`
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    pub requests: u64,
+    pub period: Duration,
+    pub key: RateLimitKey,
+}
+
+impl RateLimit {
+    pub fn new(requests: u64, period: f64, key: &str) -> Result<Self, String> {
+        if requests == 0 {
+            return Err("the requests must be at least 1".to_string());
+        }
+        if !(period > 0.0 && period.is_finite()) {
+            return Err("the period must be a positive number of seconds".to_string());
+        }
+        Ok(Self {
+            requests,
+            period: Duration::from_secs_f64(period),
+            key: RateLimitKey::parse(key)?,
+        })
+    }
+
+    pub fn from_config(config: &RateLimitConfig) -> Result<Self, String> {
+        Self::new(config.requests, config.period, &config.key)
+    }
+
+    //the value of a `rate=` route flag: `<requests>/<seconds>`, optionally followed by
+    //`/<key>`, like `10/60/header:X-API-Key`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.splitn(3, '/');
+        let requests = parts.next().and_then(|requests| requests.parse().ok());
+        let period = parts.next().and_then(|period| period.parse().ok());
+        match (requests, period) {
+            (Some(requests), Some(period)) => Self::new(requests, period, parts.next().unwrap_or("ip")),
+            _ => Err("it takes `<requests>/<seconds>[/<key>]` or `none`".to_string()),
+        }
+    }
+}
+
+//the limit of the config and the ones of the routes, with the buckets they count in
+#[derive(Clone)]
+pub struct RateLimiter {
+    default: Option<RateLimit>,
+    //by handler, None when the route is not limited at all
+    routes: HashMap<String, Option<RateLimit>>,
+    backend: Arc<dyn RateLimitBackend>,
+}
+
+//how a limited request stands, its response tells the client
+pub struct RateLimitStatus {
+    limit: RateLimit,
+    decision: Decision,
+}
+
+impl RateLimiter {
+    pub fn from_config(config: &Config) -> Self {
+        Self::with_backend(config, Arc::new(MemoryBackend::new()))
+    }
+
+    pub fn with_backend(config: &Config, backend: Arc<dyn RateLimitBackend>) -> Self {
+        Self {
+            //checked when the config was loaded
+            default: config.rate_limit.as_ref().and_then(|limit| RateLimit::from_config(limit).ok()),
+            routes: HashMap::new(),
+            backend,
+        }
+    }
+
+    pub fn set_route(&mut self, handler: String, limit: Option<RateLimit>) {
+        self.routes.insert(handler, limit);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.default.is_some() || self.routes.values().any(|limit| limit.is_some())
+    }
+
+    //takes a token for the request of the client, None when it is not limited
+    pub fn check(&self, client: &ClientInfo, headers: &Headers, handler: Option<&str>) -> Option<RateLimitStatus> {
+        //the routes with a limit of their own have their own buckets
+        let (scope, limit) = match handler.and_then(|handler| self.routes.get(handler).map(|limit| (handler, limit))) {
+            Some((_, None)) => return None,
+            Some((handler, Some(limit))) => (handler, limit),
+            None => ("*", self.default.as_ref()?),
+        };
+        let address = || match client.ip {
+            Some(ip) => format!("ip:{}", ip),
+            None => "ip:unknown".to_string(),
+        };
+        let client = match &limit.key {
+            RateLimitKey::Ip => address(),
+            RateLimitKey::Header(name) => match headers.get(name) {
+                Some(value) => format!("header:{}", value),
+                None => address(),
+            },
+            RateLimitKey::Route => format!("route:{}", handler.unwrap_or_default()),
+        };
+        let key = format!("{} {}", scope, client);
+        Some(RateLimitStatus {
+            decision: self.backend.take(&key, limit.requests, limit.period),
+            limit: limit.clone(),
+        })
+    }
+}
+
+impl RateLimitStatus {
+    pub fn allowed(&self) -> bool {
+        self.decision.allowed
+    }
+
+    //the answer when the client has no requests left
+    pub fn response(&self) -> Response {
+        let retry_after = seconds(self.decision.retry_after).max(1);
+        self.apply(Response::new(429, "text/plain; charset=utf-8", "Too Many Requests"))
+            .with_header("Retry-After", &retry_after.to_string())
+    }
+
+    //adds the `RateLimit-*` headers the handler did not set
+    pub fn apply(&self, mut response: Response) -> Response {
+        let headers = [
+            ("RateLimit-Limit", self.limit.requests.to_string()),
+            ("RateLimit-Remaining", self.decision.remaining.to_string()),
+            ("RateLimit-Reset", seconds(self.decision.reset).to_string()),
+            ("RateLimit-Policy", format!("{};w={}", self.limit.requests, seconds(self.limit.period))),
+        ];
+        for (name, value) in headers {
+            if response.header(name).is_none() {
+                response = response.with_header(name, &value);
+            }
+        }
+        response
+    }
+}
+
+//whole seconds, rounded up so the clients never come back too early
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(key: &str) -> RateLimiter {
+        RateLimiter::from_config(&Config {
+            rate_limit: Some(RateLimitConfig {
+                requests: 2,
+                period: 60.0,
+                key: key.to_string(),
+            }),
+            ..Config::default()
+        })
+    }
+
+    fn client(ip: &str) -> ClientInfo {
+        ClientInfo {
+            ip: ip.parse().ok(),
+            port: None,
+            scheme: "http".to_string(),
+            host: "example.com".to_string(),
+        }
+    }
+
+    fn headers(api_key: Option<&str>) -> Headers {
+        let mut headers = Headers::new();
+        if let Some(api_key) = api_key {
+            headers.insert("X-API-Key", api_key);
+        }
+        headers
+    }
+
+    fn allowed(limiter: &RateLimiter, ip: &str, api_key: Option<&str>, handler: Option<&str>) -> bool {
+        limiter.check(&client(ip), &headers(api_key), handler).unwrap().allowed()
+    }
+
+    #[test]
+    fn parses_the_route_flag() {
+        let limit = RateLimit::parse("10/60/header:X-API-Key").unwrap();
+        assert_eq!(limit.requests, 10);
+        assert_eq!(limit.period, Duration::from_secs(60));
+        assert_eq!(limit.key, RateLimitKey::Header("X-API-Key".to_string()));
+        assert_eq!(RateLimit::parse("5/0.5").unwrap().key, RateLimitKey::Ip);
+        assert!(RateLimit::parse("0/60").is_err());
+        assert!(RateLimit::parse("10/0").is_err());
+        assert!(RateLimit::parse("10/inf").is_err());
+        assert!(RateLimit::parse("10").is_err());
+        assert!(RateLimit::parse("10/60/cookie").is_err());
+        assert!(RateLimit::parse("10/60/header:").is_err());
+    }
+
+    #[test]
+    fn answers_429_over_the_limit() {
+        let limiter = limiter("ip");
+        let status = limiter.check(&client("10.0.0.1"), &headers(None), None).unwrap();
+        let response = status.apply(Response::new(200, "text/plain", "ok"));
+        assert_eq!(response.header("RateLimit-Limit"), Some("2"));
+        assert_eq!(response.header("RateLimit-Remaining"), Some("1"));
+        assert_eq!(response.header("RateLimit-Reset"), Some("30"));
+        assert_eq!(response.header("RateLimit-Policy"), Some("2;w=60"));
+        assert!(allowed(&limiter, "10.0.0.1", None, None));
+        let status = limiter.check(&client("10.0.0.1"), &headers(None), None).unwrap();
+        assert!(!status.allowed());
+        let response = status.response();
+        assert_eq!(response.code, 429);
+        //a token every 30 seconds, rounded up
+        let retry_after: u64 = response.header("Retry-After").unwrap().parse().unwrap();
+        assert!((29..=30).contains(&retry_after));
+        assert_eq!(response.header("RateLimit-Remaining"), Some("0"));
+    }
+
+    #[test]
+    fn keeps_the_headers_of_the_handler() {
+        let status = limiter("ip").check(&client("10.0.0.1"), &headers(None), None).unwrap();
+        let response = status.apply(Response::new(200, "text/plain", "ok").with_header("RateLimit-Limit", "5"));
+        assert_eq!(response.header("RateLimit-Limit"), Some("5"));
+    }
+
+    #[test]
+    fn counts_the_clients_apart() {
+        let limiter = limiter("ip");
+        for _ in 0..2 {
+            assert!(allowed(&limiter, "10.0.0.1", None, None));
+        }
+        assert!(!allowed(&limiter, "10.0.0.1", None, None));
+        assert!(allowed(&limiter, "10.0.0.2", None, None));
+    }
+
+    #[test]
+    fn counts_by_header_or_by_address_without_it() {
+        let limiter = limiter("header:X-API-Key");
+        for _ in 0..2 {
+            assert!(allowed(&limiter, "10.0.0.1", Some("a"), None));
+        }
+        //the same key from another address
+        assert!(!allowed(&limiter, "10.0.0.2", Some("a"), None));
+        assert!(allowed(&limiter, "10.0.0.1", Some("b"), None));
+        for _ in 0..2 {
+            assert!(allowed(&limiter, "10.0.0.1", None, None));
+        }
+        assert!(!allowed(&limiter, "10.0.0.1", None, None));
+    }
+
+    #[test]
+    fn counts_the_routes_apart() {
+        let mut limiter = limiter("ip");
+        limiter.set_route("app::search".to_string(), Some(RateLimit::new(1, 60.0, "route").unwrap()));
+        limiter.set_route("app::health".to_string(), None);
+        assert!(allowed(&limiter, "10.0.0.1", None, Some("app::search")));
+        assert!(!allowed(&limiter, "10.0.0.2", None, Some("app::search")));
+        //the default limit has buckets of its own
+        assert!(allowed(&limiter, "10.0.0.1", None, Some("app::other")));
+        assert!(limiter.check(&client("10.0.0.1"), &headers(None), Some("app::health")).is_none());
+        assert!(limiter.is_enabled());
+        assert!(RateLimiter::from_config(&Config::default()).check(&client("10.0.0.1"), &headers(None), None).is_none());
+    }
+}
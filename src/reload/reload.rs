@@ -0,0 +1,50 @@
+use crate::visit_dirs;
+use std::fs::DirEntry;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+//how often the project tree is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+type Snapshot = Vec<(PathBuf, Option<SystemTime>)>;
+
+//watches the python files of the project, sending a message every time one of them
+//is created, modified or removed
+pub fn watch(project_path: &str) -> Receiver<()> {
+    let (tx, rx) = mpsc::channel();
+    let project_path = project_path.to_string();
+    thread::spawn(move || {
+        let mut last_snapshot = snapshot(&project_path);
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = snapshot(&project_path);
+            if current != last_snapshot {
+                last_snapshot = current;
+                if tx.send(()).is_err() {
+                    //the server is gone
+                    break;
+                }
+            }
+        }
+    });
+    rx
+}
+
+//uses the same exclusions as the route discovery, so changes in the venv are ignored
+fn snapshot(project_path: &str) -> Snapshot {
+    let mut container: Snapshot = Vec::new();
+    _ = visit_dirs(Path::new(project_path), &visit_modified_time, &mut container);
+    container.sort();
+    container
+}
+
+fn visit_modified_time(entry: &DirEntry, container: &mut Snapshot) {
+    let path = entry.path();
+    if path.extension().map(|ext| ext == "py").unwrap_or(false) {
+        let modified = entry.metadata().and_then(|metadata| metadata.modified()).ok();
+        container.push((path, modified));
+    }
+}
@@ -0,0 +1,180 @@
+extern crate cookie;
+extern crate pyo3;
+
+use crate::request::{Headers, MultiDict};
+use cookie::{Cookie, CookieJar, Key};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::PyClassInitializer;
+
+//parses every `Cookie` header into a map from the cookie names to their percent decoded values.
+//invalid pairs are skipped, like browsers do
+pub fn parse_cookies(headers: &Headers) -> MultiDict {
+    let mut cookies = MultiDict::new();
+    for header in headers.get_all("Cookie") {
+        for cookie in Cookie::split_parse_encoded(header.as_str()).flatten() {
+            cookies.insert(cookie.name(), cookie.value());
+        }
+    }
+    cookies
+}
+
+//the key signing and encrypting the cookies, derived from the `secret_key` of the config
+pub fn cookie_key(secret_key: &str) -> Key {
+    Key::derive_from(secret_key.as_bytes())
+}
+
+//the signature of a cookie only covers its value, every name gets a key of its own so a signed
+//value is not accepted under another name. the encrypted cookies already use their name
+fn signing_key(key: &Key, name: &str) -> Key {
+    Key::derive_from(&[key.signing(), name.as_bytes()].concat())
+}
+
+//the value of a signed cookie, if its signature is valid
+pub fn verify_signed(key: &Key, name: &str, value: &str) -> Option<String> {
+    let mut jar = CookieJar::new();
+    jar.add_original(Cookie::new(name.to_string(), value.to_string()));
+    let cookie = jar.signed(&signing_key(key, name)).get(name)?;
+    Some(cookie.value().to_string())
+}
+
+//the value of an encrypted cookie, if it was encrypted with the key and not tampered with
+pub fn decrypt_private(key: &Key, name: &str, value: &str) -> Option<String> {
+    let mut jar = CookieJar::new();
+    jar.add_original(Cookie::new(name.to_string(), value.to_string()));
+    let cookie = jar.private(key).get(name)?;
+    Some(cookie.value().to_string())
+}
+
+//the `name=value` pair of a signed cookie, percent encoded to be sent in a `Set-Cookie` header
+pub fn sign_cookie(key: &Key, name: &str, value: &str) -> String {
+    let mut jar = CookieJar::new();
+    jar.signed_mut(&signing_key(key, name)).add(Cookie::new(name.to_string(), value.to_string()));
+    let cookie = jar.get(name).expect("the cookie was just added");
+    cookie.encoded().stripped().to_string()
+}
+
+//the `name=value` pair of an encrypted cookie, the value can not be read by the client
+pub fn encrypt_cookie(key: &Key, name: &str, value: &str) -> String {
+    let mut jar = CookieJar::new();
+    jar.private_mut(key).add(Cookie::new(name.to_string(), value.to_string()));
+    let cookie = jar.get(name).expect("the cookie was just added");
+    cookie.encoded().stripped().to_string()
+}
+
+//what python handlers get as the cookies: a `MultiDict` that can also verify, sign
+//and encrypt cookies when the config has a `secret_key`
+#[pyclass(extends = MultiDict)]
+pub struct Cookies {
+    key: Option<Key>,
+}
+
+impl Cookies {
+    pub fn to_python(py: Python, cookies: MultiDict, key: Option<Key>) -> PyResult<Py<Cookies>> {
+        Py::new(py, PyClassInitializer::from(cookies).add_subclass(Cookies { key }))
+    }
+
+    fn key(&self) -> PyResult<&Key> {
+        self.key
+            .as_ref()
+            .ok_or_else(|| PyRuntimeError::new_err("signed and encrypted cookies need a `secret_key` in the config"))
+    }
+}
+
+#[pymethods]
+impl Cookies {
+    //the value of a signed cookie, None when it is missing or its signature is wrong
+    fn signed(self_: PyRef<Self>, name: &str) -> PyResult<Option<String>> {
+        let key = self_.key()?;
+        let cookies: &MultiDict = self_.as_ref();
+        Ok(cookies
+            .get_all(name)
+            .into_iter()
+            .find_map(|value| verify_signed(key, name, value)))
+    }
+
+    //the value of an encrypted cookie, None when it is missing or can not be decrypted
+    fn private(self_: PyRef<Self>, name: &str) -> PyResult<Option<String>> {
+        let key = self_.key()?;
+        let cookies: &MultiDict = self_.as_ref();
+        Ok(cookies
+            .get_all(name)
+            .into_iter()
+            .find_map(|value| decrypt_private(key, name, value)))
+    }
+
+    fn sign(&self, name: &str, value: &str) -> PyResult<String> {
+        Ok(sign_cookie(self.key()?, name, value))
+    }
+
+    fn encrypt(&self, name: &str, value: &str) -> PyResult<String> {
+        Ok(encrypt_cookie(self.key()?, name, value))
+    }
+
+    fn __repr__(self_: PyRef<Self>) -> String {
+        let cookies: &MultiDict = self_.as_ref();
+        format!("Cookies({:?})", cookies.iter().collect::<Vec<_>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "a secret key of at least 32 bytes, for the tests";
+    const OTHER_SECRET: &str = "another secret key of at least 32 bytes, for the tests";
+
+    //the value the client sends back for the `name=value` pair it was given
+    fn received(pair: &str) -> (String, String) {
+        let mut headers = Headers::new();
+        headers.insert("Cookie", pair);
+        let cookies = parse_cookies(&headers);
+        let (name, value) = cookies.iter().next().unwrap();
+        (name.clone(), value.clone())
+    }
+
+    //changes a character in the middle of the value
+    fn tampered(value: &str) -> String {
+        let mut chars: Vec<char> = value.chars().collect();
+        let middle = chars.len() / 2;
+        chars[middle] = if chars[middle] == 'A' { 'B' } else { 'A' };
+        chars.into_iter().collect()
+    }
+
+    #[test]
+    fn parses_every_cookie_header() {
+        let mut headers = Headers::new();
+        headers.insert("Cookie", "a=1; b=hello%20world; invalid; a=2");
+        headers.insert("cookie", "c=3");
+        let cookies = parse_cookies(&headers);
+        assert_eq!(cookies.get_all("a"), vec!["1", "2"]);
+        assert_eq!(cookies.get("b").map(String::as_str), Some("hello world"));
+        assert_eq!(cookies.get("c").map(String::as_str), Some("3"));
+        assert!(!cookies.contains("invalid"));
+    }
+
+    #[test]
+    fn verifies_signed_cookies() {
+        let key = cookie_key(SECRET);
+        let (name, value) = received(&sign_cookie(&key, "session", "user=1; admin"));
+        assert_eq!(name, "session");
+        assert_eq!(verify_signed(&key, &name, &value).as_deref(), Some("user=1; admin"));
+        assert_eq!(verify_signed(&key, &name, &tampered(&value)), None);
+        assert_eq!(verify_signed(&cookie_key(OTHER_SECRET), &name, &value), None);
+        //the signature is only valid for the cookie it was made for
+        assert_eq!(verify_signed(&key, "other", &value), None);
+        assert_eq!(verify_signed(&key, &name, "user=1; admin"), None);
+    }
+
+    #[test]
+    fn decrypts_private_cookies() {
+        let key = cookie_key(SECRET);
+        let (name, value) = received(&encrypt_cookie(&key, "session", "user=1"));
+        assert!(!value.contains("user=1"));
+        assert_eq!(decrypt_private(&key, &name, &value).as_deref(), Some("user=1"));
+        assert_eq!(decrypt_private(&key, &name, &tampered(&value)), None);
+        assert_eq!(decrypt_private(&cookie_key(OTHER_SECRET), &name, &value), None);
+        assert_eq!(decrypt_private(&key, "other", &value), None);
+        assert_eq!(decrypt_private(&key, &name, "user=1"), None);
+    }
+}
@@ -0,0 +1,116 @@
+extern crate pyo3;
+
+use crate::request::MultiDict;
+use pyo3::exceptions::PyKeyError;
+use pyo3::prelude::*;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+//the headers of a request, names are compared ignoring their case and
+//every value of a repeated header is kept, in the order they were received
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct Headers {
+    entries: MultiDict,
+}
+
+impl Default for Headers {
+    fn default() -> Self {
+        Self {
+            entries: MultiDict::case_insensitive(),
+        }
+    }
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.entries.insert(name, value);
+    }
+
+    //the first value of the header
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries.get(name)
+    }
+
+    //every value of the header
+    pub fn get_all(&self, name: &str) -> Vec<&String> {
+        self.entries.get_all(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, String)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[pymethods]
+impl Headers {
+    #[pyo3(name = "get")]
+    #[args(default = "None")]
+    fn py_get(&self, py: Python, name: &str, default: Option<PyObject>) -> PyObject {
+        match self.get(name) {
+            Some(value) => value.into_py(py),
+            None => default.unwrap_or_else(|| py.None()),
+        }
+    }
+
+    #[pyo3(name = "getall")]
+    fn py_getall(&self, name: &str) -> Vec<String> {
+        self.get_all(name).into_iter().cloned().collect()
+    }
+
+    #[pyo3(name = "keys")]
+    fn py_keys(&self) -> Vec<String> {
+        self.entries.keys().into_iter().cloned().collect()
+    }
+
+    #[pyo3(name = "items")]
+    fn py_items(&self) -> Vec<(String, String)> {
+        self.iter().cloned().collect()
+    }
+
+    fn __getitem__(&self, name: &str) -> PyResult<String> {
+        self.get(name)
+            .cloned()
+            .ok_or_else(|| PyKeyError::new_err(name.to_string()))
+    }
+
+    fn __contains__(&self, name: &str) -> bool {
+        self.contains(name)
+    }
+
+    fn __len__(&self) -> usize {
+        self.entries.keys().len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Headers({:?})", self.py_items())
+    }
+}
+
+//serialized as a map, the values of a repeated header are joined like in a single header line
+impl Serialize for Headers {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let groups = self.entries.grouped();
+        let mut map = serializer.serialize_map(Some(groups.len()))?;
+        for (name, values) in groups {
+            let values: Vec<&str> = values.into_iter().map(|v| v.as_str()).collect();
+            map.serialize_entry(name, &values.join(", "))?;
+        }
+        map.end()
+    }
+}
@@ -0,0 +1,146 @@
+extern crate encoding_rs;
+
+use crate::request::RequestError;
+use encoding_rs::Encoding;
+use std::borrow::Cow;
+
+//a parsed `Content-Type`, like `application/json; charset=utf-8`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    //the type and subtype, lowercased
+    pub essence: String,
+    //the parameters, with their names lowercased
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';');
+        let essence = parts.next()?.trim().to_ascii_lowercase();
+        let (main_type, sub_type) = essence.split_once('/')?;
+        if main_type.is_empty() || sub_type.is_empty() || essence.contains(char::is_whitespace) {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                let value = value.trim();
+                //quoted values can escape characters with a backslash
+                let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                    let mut unquoted = String::new();
+                    let mut chars = value[1..value.len() - 1].chars();
+                    while let Some(ch) = chars.next() {
+                        match ch {
+                            '\\' => unquoted.extend(chars.next()),
+                            _ => unquoted.push(ch),
+                        }
+                    }
+                    unquoted
+                } else {
+                    value.to_string()
+                };
+                params.push((name.trim().to_ascii_lowercase(), value));
+            }
+        }
+        Some(Self { essence, params })
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    //`application/json`, or any `+json` type like `application/problem+json`
+    pub fn is_json(&self) -> bool {
+        self.essence == "application/json" || self.essence.ends_with("+json")
+    }
+
+    //`application/xml`, `text/xml`, or any `+xml` type like `application/atom+xml`
+    pub fn is_xml(&self) -> bool {
+        self.essence == "application/xml" || self.essence == "text/xml" || self.essence.ends_with("+xml")
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.essence.starts_with("multipart/")
+    }
+
+    //decodes the body with the charset of the media type, utf-8 when it has none.
+    //strict decoding fails on invalid bytes, lossy decoding replaces them
+    pub fn decode<'a>(&self, body: &'a [u8], strict: bool) -> Result<Cow<'a, str>, RequestError> {
+        let encoding = match self.param("charset") {
+            Some(charset) => Encoding::for_label(charset.as_bytes()).ok_or_else(|| {
+                RequestError::UnsupportedMediaType(format!("unknown charset {}", charset))
+            })?,
+            None => encoding_rs::UTF_8,
+        };
+        if !strict {
+            return Ok(encoding.decode_with_bom_removal(body).0);
+        }
+        encoding
+            .decode_without_bom_handling_and_without_replacement(body)
+            .ok_or_else(|| RequestError::InvalidBody {
+                reason: format!("body is not valid {}", encoding.name()),
+                line: None,
+                column: None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn media_type(value: &str) -> MediaType {
+        MediaType::parse(value).unwrap()
+    }
+
+    #[test]
+    fn parses_the_essence_and_parameters() {
+        let parsed = media_type(r#"Multipart/Form-Data; Boundary="a \"b\""; charset=UTF-8"#);
+        assert_eq!(parsed.essence, "multipart/form-data");
+        assert_eq!(parsed.param("boundary"), Some(r#"a "b""#));
+        assert_eq!(parsed.param("CHARSET"), Some("UTF-8"));
+        assert!(parsed.is_multipart());
+        assert_eq!(MediaType::parse("json"), None);
+        assert_eq!(MediaType::parse("application/"), None);
+        assert_eq!(MediaType::parse("text /plain"), None);
+    }
+
+    #[test]
+    fn recognizes_json_and_xml_types() {
+        assert!(media_type("application/json; charset=utf-8").is_json());
+        assert!(media_type("application/problem+json").is_json());
+        assert!(media_type("Application/Vnd.Api+JSON").is_json());
+        assert!(!media_type("text/json-seq").is_json());
+        assert!(!media_type("application/jsonx").is_json());
+        assert!(media_type("text/xml").is_xml());
+        assert!(media_type("application/atom+xml").is_xml());
+        assert!(!media_type("application/json").is_xml());
+    }
+
+    #[test]
+    fn decodes_with_the_charset() {
+        assert_eq!(media_type("text/plain; charset=latin1").decode(b"caf\xe9", true).unwrap(), "café");
+        assert_eq!(media_type("application/json").decode("café".as_bytes(), true).unwrap(), "café");
+        assert_eq!(media_type("application/json; charset=\"utf-8\"").decode(b"{}", true).unwrap(), "{}");
+    }
+
+    #[test]
+    fn refuses_invalid_bodies_only_when_strict() {
+        let invalid = [b'a', 0xff, b'b'];
+        let json = media_type("application/json");
+        assert!(matches!(json.decode(&invalid, true), Err(RequestError::InvalidBody { .. })));
+        assert_eq!(json.decode(&invalid, false).unwrap(), "a\u{fffd}b");
+        //a byte order mark is dropped from the lossy text
+        assert_eq!(json.decode(b"\xef\xbb\xbfok", false).unwrap(), "ok");
+    }
+
+    #[test]
+    fn refuses_unknown_charsets() {
+        let err = media_type("text/plain; charset=klingon").decode(b"x", false).unwrap_err();
+        assert_eq!(err.code(), 415);
+    }
+}
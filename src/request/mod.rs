@@ -1,3 +1,22 @@
+pub mod cookies;
+pub mod headers;
+pub mod media_type;
+pub mod multidict;
+pub mod multipart;
+pub mod parser;
+pub mod proxy;
+pub mod py_request;
+#[allow(clippy::module_inception)]
 pub mod request;
+pub mod xml;
 
+pub use cookies::*;
+pub use headers::*;
+pub use media_type::*;
+pub use multidict::*;
+pub use multipart::*;
+pub use parser::*;
+pub use proxy::*;
+pub use py_request::*;
 pub use request::*;
+pub use xml::*;
@@ -0,0 +1,172 @@
+extern crate form_urlencoded;
+extern crate pyo3;
+
+use pyo3::exceptions::PyKeyError;
+use pyo3::prelude::*;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+//a map where every key can have several values, in the order they were received.
+//used for the query string, the url encoded forms and the cookies, and
+//ignoring the case of the keys, for the headers
+#[pyclass(subclass)]
+#[derive(Debug, Clone, Default)]
+pub struct MultiDict {
+    entries: Vec<(String, String)>,
+    ignore_case: bool,
+}
+
+impl MultiDict {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn case_insensitive() -> Self {
+        Self {
+            entries: Vec::new(),
+            ignore_case: true,
+        }
+    }
+
+    //parses `application/x-www-form-urlencoded` data, the format of the query strings:
+    //the keys and values are percent decoded and `+` is a space
+    pub fn parse_urlencoded(raw: &[u8]) -> Self {
+        let entries = form_urlencoded::parse(raw)
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Self {
+            entries,
+            ignore_case: false,
+        }
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.entries.push((key.to_string(), value.to_string()));
+    }
+
+    //the first value of the key
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.entries
+            .iter()
+            .find(|(name, _)| self.matches(name, key))
+            .map(|(_, value)| value)
+    }
+
+    //every value of the key
+    pub fn get_all(&self, key: &str) -> Vec<&String> {
+        self.entries
+            .iter()
+            .filter(|(name, _)| self.matches(name, key))
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, String)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    //the distinct keys, in the order and with the case of their first appearance
+    pub fn keys(&self) -> Vec<&String> {
+        self.grouped().into_iter().map(|(key, _)| key).collect()
+    }
+
+    //every distinct key with all of its values, in a single pass over the entries
+    pub fn grouped(&self) -> Vec<(&String, Vec<&String>)> {
+        let mut positions: HashMap<Cow<str>, usize> = HashMap::new();
+        let mut groups: Vec<(&String, Vec<&String>)> = Vec::new();
+        for (name, value) in &self.entries {
+            match positions.get(&self.normalize(name)) {
+                Some(&position) => groups[position].1.push(value),
+                None => {
+                    positions.insert(self.normalize(name), groups.len());
+                    groups.push((name, vec![value]));
+                }
+            }
+        }
+        groups
+    }
+
+    fn matches(&self, name: &str, key: &str) -> bool {
+        match self.ignore_case {
+            true => name.eq_ignore_ascii_case(key),
+            false => name == key,
+        }
+    }
+
+    fn normalize<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match self.ignore_case {
+            true => Cow::Owned(name.to_ascii_lowercase()),
+            false => Cow::Borrowed(name),
+        }
+    }
+}
+
+#[pymethods]
+impl MultiDict {
+    #[pyo3(name = "get")]
+    #[args(default = "None")]
+    fn py_get(&self, py: Python, key: &str, default: Option<PyObject>) -> PyObject {
+        match self.get(key) {
+            Some(value) => value.into_py(py),
+            None => default.unwrap_or_else(|| py.None()),
+        }
+    }
+
+    #[pyo3(name = "getall")]
+    fn py_getall(&self, key: &str) -> Vec<String> {
+        self.get_all(key).into_iter().cloned().collect()
+    }
+
+    #[pyo3(name = "keys")]
+    fn py_keys(&self) -> Vec<String> {
+        self.keys().into_iter().cloned().collect()
+    }
+
+    #[pyo3(name = "items")]
+    fn py_items(&self) -> Vec<(String, String)> {
+        self.entries.clone()
+    }
+
+    fn __getitem__(&self, key: &str) -> PyResult<String> {
+        self.get(key)
+            .cloned()
+            .ok_or_else(|| PyKeyError::new_err(key.to_string()))
+    }
+
+    fn __contains__(&self, key: &str) -> bool {
+        self.contains(key)
+    }
+
+    fn __len__(&self) -> usize {
+        self.keys().len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("MultiDict({:?})", self.entries)
+    }
+}
+
+//serialized as a map from every key to the list of its values
+impl Serialize for MultiDict {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let groups = self.grouped();
+        let mut map = serializer.serialize_map(Some(groups.len()))?;
+        for (key, values) in groups {
+            map.serialize_entry(key, &values)?;
+        }
+        map.end()
+    }
+}
@@ -0,0 +1,406 @@
+extern crate pyo3;
+extern crate tempfile;
+
+use crate::request::parser::find;
+use crate::request::{MultiDict, RequestError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use serde::Serialize;
+use std::io::{Read, Write};
+use tempfile::NamedTempFile;
+
+//the most bytes the headers of a single part can take
+const MAX_PART_HEAD_SIZE: usize = 8192;
+//how much of the body is read at once
+const CHUNK_SIZE: usize = 64 * 1024;
+
+//where the content of an uploaded file is kept
+#[derive(Debug)]
+pub enum UploadStorage {
+    Memory(Vec<u8>),
+    //files bigger than the `upload_memory_size` are written to a temporary file,
+    //removed once the request is over
+    File(NamedTempFile),
+}
+
+//a file received in a `multipart/form-data` body
+#[derive(Serialize, Debug)]
+pub struct UploadedFile {
+    //the name of the form field
+    pub name: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: usize,
+    #[serde(skip)]
+    pub storage: UploadStorage,
+}
+
+//what python handlers get for every uploaded file
+#[pyclass(name = "UploadedFile")]
+pub struct PyUploadedFile {
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    filename: String,
+    #[pyo3(get)]
+    content_type: String,
+    #[pyo3(get)]
+    size: usize,
+    //a binary file object, opened at the start of the content
+    #[pyo3(get)]
+    file: PyObject,
+}
+
+#[pymethods]
+impl PyUploadedFile {
+    fn read(&self, py: Python) -> PyResult<PyObject> {
+        self.file.call_method0(py, "read")
+    }
+
+    fn __repr__(&self) -> String {
+        format!("UploadedFile(name={:?}, filename={:?}, size={})", self.name, self.filename, self.size)
+    }
+}
+
+impl UploadedFile {
+    pub fn to_python(&self, py: Python) -> PyResult<Py<PyUploadedFile>> {
+        let file = match &self.storage {
+            UploadStorage::Memory(data) => py
+                .import("io")?
+                .getattr("BytesIO")?
+                .call1((PyBytes::new(py, data),))?
+                .into(),
+            //the file stays readable from python once the temporary file is removed
+            UploadStorage::File(temp_file) => py
+                .import("builtins")?
+                .getattr("open")?
+                .call1((temp_file.path().to_string_lossy().to_string(), "rb"))?
+                .into(),
+        };
+        Py::new(
+            py,
+            PyUploadedFile {
+                name: self.name.clone(),
+                filename: self.filename.clone(),
+                content_type: self.content_type.clone(),
+                size: self.size,
+                file,
+            },
+        )
+    }
+}
+
+//parses a `multipart/form-data` body, the fields go to the form and the files are returned
+pub fn parse_multipart<R: Read>(
+    reader: R,
+    boundary: &str,
+    memory_size: usize,
+    form: &mut MultiDict,
+) -> Result<Vec<UploadedFile>, RequestError> {
+    if boundary.is_empty() || boundary.len() > 70 {
+        return Err(malformed("invalid boundary"));
+    }
+    let mut body = BodyReader::new(reader);
+    //the delimiter always follows a line break, the first one is added to find it at the start
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    body.buffer.extend_from_slice(b"\r\n");
+
+    //skip the preamble
+    body.read_until(&delimiter, &mut ())?;
+    let mut files = Vec::new();
+    loop {
+        //the last delimiter is followed by `--`, the others by a line break
+        body.fill(2)?;
+        if body.buffer.starts_with(b"--") {
+            return Ok(files);
+        }
+        if !body.buffer.starts_with(b"\r\n") {
+            return Err(malformed("invalid delimiter"));
+        }
+        body.consume(2);
+
+        let (name, filename, content_type) = body.read_part_head()?;
+        match filename {
+            None => {
+                let mut value: Vec<u8> = Vec::new();
+                body.read_until(&delimiter, &mut value)?;
+                form.insert(&name, &String::from_utf8_lossy(&value));
+            }
+            Some(filename) => {
+                let mut spool = Spool {
+                    memory: Vec::new(),
+                    file: None,
+                    memory_size,
+                };
+                let size = body.read_until(&delimiter, &mut spool)?;
+                files.push(UploadedFile {
+                    name,
+                    filename,
+                    content_type: content_type.unwrap_or_else(|| "application/octet-stream".to_string()),
+                    size,
+                    storage: spool.into_storage()?,
+                });
+            }
+        }
+    }
+}
+
+fn malformed(reason: &str) -> RequestError {
+    RequestError::BadRequest(format!("malformed multipart body: {}", reason))
+}
+
+//where the content of a part goes while it is read
+trait PartSink {
+    fn write_part(&mut self, data: &[u8]) -> Result<(), RequestError>;
+}
+
+//discards the content, used for the preamble
+impl PartSink for () {
+    fn write_part(&mut self, _data: &[u8]) -> Result<(), RequestError> {
+        Ok(())
+    }
+}
+
+impl PartSink for Vec<u8> {
+    fn write_part(&mut self, data: &[u8]) -> Result<(), RequestError> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+//kept in memory until it gets bigger than `memory_size`, then written to a temporary file
+struct Spool {
+    memory: Vec<u8>,
+    file: Option<NamedTempFile>,
+    memory_size: usize,
+}
+
+impl Spool {
+    fn into_storage(self) -> Result<UploadStorage, RequestError> {
+        match self.file {
+            Some(mut file) => {
+                file.flush().map_err(|_| malformed("could not store the upload"))?;
+                Ok(UploadStorage::File(file))
+            }
+            None => Ok(UploadStorage::Memory(self.memory)),
+        }
+    }
+}
+
+impl PartSink for Spool {
+    fn write_part(&mut self, data: &[u8]) -> Result<(), RequestError> {
+        let storage_error = |_| malformed("could not store the upload");
+        if self.file.is_none() && self.memory.len() + data.len() > self.memory_size {
+            let mut temp_file = NamedTempFile::new().map_err(storage_error)?;
+            temp_file.write_all(&self.memory).map_err(storage_error)?;
+            self.memory.clear();
+            self.file = Some(temp_file);
+        }
+        match &mut self.file {
+            Some(file) => file.write_all(data).map_err(storage_error),
+            None => {
+                self.memory.extend_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+}
+
+//reads the body by chunks, keeping the bytes not consumed yet
+struct BodyReader<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> BodyReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            eof: false,
+        }
+    }
+
+    //reads one more chunk, returns false at the end of the body
+    fn read_chunk(&mut self) -> Result<bool, RequestError> {
+        if self.eof {
+            return Ok(false);
+        }
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let bytes_read = self
+            .reader
+            .read(&mut chunk)
+            .map_err(|_| malformed("could not read the body"))?;
+        if bytes_read == 0 {
+            self.eof = true;
+            return Ok(false);
+        }
+        self.buffer.extend_from_slice(&chunk[..bytes_read]);
+        Ok(true)
+    }
+
+    //makes sure at least `length` bytes are buffered
+    fn fill(&mut self, length: usize) -> Result<(), RequestError> {
+        while self.buffer.len() < length {
+            if !self.read_chunk()? {
+                return Err(malformed("unexpected end of the body"));
+            }
+        }
+        Ok(())
+    }
+
+    fn consume(&mut self, length: usize) {
+        self.buffer.drain(..length);
+    }
+
+    //moves everything before the delimiter to the sink and consumes the delimiter,
+    //returns the number of bytes given to the sink
+    fn read_until(&mut self, delimiter: &[u8], sink: &mut dyn PartSink) -> Result<usize, RequestError> {
+        let mut written = 0;
+        loop {
+            if let Some(idx) = find(&self.buffer, delimiter) {
+                sink.write_part(&self.buffer[..idx])?;
+                self.consume(idx + delimiter.len());
+                return Ok(written + idx);
+            }
+            //the end of the buffer can be the start of the delimiter, keep it
+            let safe = self.buffer.len().saturating_sub(delimiter.len() - 1);
+            sink.write_part(&self.buffer[..safe])?;
+            self.consume(safe);
+            written += safe;
+            if !self.read_chunk()? {
+                return Err(malformed("missing closing delimiter"));
+            }
+        }
+    }
+
+    //reads the headers of a part, returns the field name, the file name and the content type
+    fn read_part_head(&mut self) -> Result<(String, Option<String>, Option<String>), RequestError> {
+        let head_end = loop {
+            if let Some(idx) = find(&self.buffer, b"\r\n\r\n") {
+                break idx;
+            }
+            if self.buffer.len() > MAX_PART_HEAD_SIZE || !self.read_chunk()? {
+                return Err(malformed("invalid part headers"));
+            }
+        };
+        let head = String::from_utf8_lossy(&self.buffer[..head_end]).to_string();
+        self.consume(head_end + 4);
+
+        let mut name = None;
+        let mut filename = None;
+        let mut content_type = None;
+        for line in head.split("\r\n") {
+            let (key, value) = match line.split_once(':') {
+                Some(header) => header,
+                None => return Err(malformed("invalid part header")),
+            };
+            if key.trim().eq_ignore_ascii_case("Content-Disposition") {
+                for param in value.split(';').skip(1) {
+                    if let Some((key, value)) = param.split_once('=') {
+                        let value = value.trim().trim_matches('"').to_string();
+                        match key.trim() {
+                            "name" => name = Some(value),
+                            "filename" => filename = Some(value),
+                            _ => {}
+                        }
+                    }
+                }
+            } else if key.trim().eq_ignore_ascii_case("Content-Type") {
+                content_type = Some(value.trim().to_string());
+            }
+        }
+        match name {
+            Some(name) => Ok((name, filename, content_type)),
+            None => Err(malformed("part without a name")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //hands the body over a few bytes at a time, so the delimiters end up split between reads
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let length = self.0.len().min(buf.len()).min(7);
+            buf[..length].copy_from_slice(&self.0[..length]);
+            self.0 = &self.0[length..];
+            Ok(length)
+        }
+    }
+
+    fn body(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = b"preamble".to_vec();
+        for (name, filename, content) in parts {
+            body.extend_from_slice(b"\r\n--b0undary\r\n");
+            let disposition = match filename {
+                Some(filename) => format!("Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\r\n", name, filename),
+                None => format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name),
+            };
+            body.extend_from_slice(disposition.as_bytes());
+            body.extend_from_slice(content);
+        }
+        body.extend_from_slice(b"\r\n--b0undary--\r\n");
+        body
+    }
+
+    fn content(file: &UploadedFile) -> Vec<u8> {
+        match &file.storage {
+            UploadStorage::Memory(data) => data.clone(),
+            UploadStorage::File(temp_file) => std::fs::read(temp_file.path()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_fields_and_files() {
+        let raw = body(&[("title", None, b"hello"), ("title", None, b"again"), ("doc", Some("a.txt"), b"line\r\n--b0und")]);
+        let mut form = MultiDict::new();
+        let files = parse_multipart(Trickle(&raw), "b0undary", 1024, &mut form).unwrap();
+        assert_eq!(form.get_all("title"), vec!["hello", "again"]);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "doc");
+        assert_eq!(files[0].filename, "a.txt");
+        assert_eq!(files[0].content_type, "application/octet-stream");
+        assert_eq!(content(&files[0]), b"line\r\n--b0und");
+    }
+
+    #[test]
+    fn spools_the_files_bigger_than_the_memory_size() {
+        let small = vec![b'a'; 64];
+        let big = vec![b'b'; 65];
+        let raw = body(&[("small", Some("small.bin"), &small), ("big", Some("big.bin"), &big)]);
+        let files = parse_multipart(Trickle(&raw), "b0undary", 64, &mut MultiDict::new()).unwrap();
+        assert!(matches!(files[0].storage, UploadStorage::Memory(_)));
+        assert!(matches!(files[1].storage, UploadStorage::File(_)));
+        assert_eq!((files[0].size, files[1].size), (64, 65));
+        assert_eq!(content(&files[0]), small);
+        assert_eq!(content(&files[1]), big);
+    }
+
+    #[test]
+    fn refuses_invalid_boundaries() {
+        let raw = body(&[("title", None, b"hello")]);
+        assert_eq!(parse_multipart(raw.as_slice(), "", 1024, &mut MultiDict::new()).unwrap_err().code(), 400);
+        let long = "b".repeat(71);
+        assert_eq!(parse_multipart(raw.as_slice(), &long, 1024, &mut MultiDict::new()).unwrap_err().code(), 400);
+        //the body does not use the announced boundary
+        assert_eq!(parse_multipart(raw.as_slice(), "other", 1024, &mut MultiDict::new()).unwrap_err().code(), 400);
+    }
+
+    #[test]
+    fn refuses_malformed_bodies() {
+        let parse = |raw: &[u8]| parse_multipart(raw, "b0undary", 1024, &mut MultiDict::new()).map(|_| ());
+        //without the closing delimiter
+        let mut raw = body(&[("title", None, b"hello")]);
+        raw.truncate(raw.len() - b"\r\n--b0undary--\r\n".len());
+        assert!(parse(&raw).is_err());
+        assert!(parse(b"\r\n--b0undary\r\nContent-Disposition: form-data\r\n\r\nx\r\n--b0undary--").is_err());
+        assert!(parse(b"\r\n--b0undary\r\nno colon\r\n\r\nx\r\n--b0undary--").is_err());
+        assert!(parse(b"\r\n--b0undaryXX").is_err());
+    }
+}
@@ -0,0 +1,698 @@
+extern crate httparse;
+extern crate percent_encoding;
+extern crate tempfile;
+extern crate tokio;
+
+use crate::config::Config;
+use crate::request::{Headers, MediaType, RequestMethod};
+use crate::response::Response;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::time::Instant;
+
+//the most headers a request can have
+pub const MAX_HEADERS: usize = 64;
+//the most bytes the request line and the headers can take together
+pub const MAX_HEAD_SIZE: usize = 16384;
+//the longest request target we accept
+pub const MAX_URI_LENGTH: usize = 8192;
+//the longest line announcing a chunk, with its extensions
+const MAX_CHUNK_LINE: usize = 4096;
+
+//why a request could not be parsed, every error is answered without reaching python
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    //the request is malformed
+    BadRequest(String),
+    //the connection was closed before the whole request was received
+    Incomplete,
+    //the request target is longer than `MAX_URI_LENGTH`
+    UriTooLong,
+    //there are more than `MAX_HEADERS` headers, or they take more than `MAX_HEAD_SIZE`
+    HeadersTooLarge,
+    //the body is bigger than the configured limits
+    PayloadTooLarge,
+    //the method or the transfer encoding is not supported by the server
+    NotImplemented(String),
+    //the body does not match its content type, like invalid json.
+    //the position of the error is given when it is known
+    InvalidBody {
+        reason: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    //the body uses a charset we can not decode
+    UnsupportedMediaType(String),
+    //the client took too long to send the request
+    Timeout,
+}
+
+impl RequestError {
+    pub fn code(&self) -> i32 {
+        match self {
+            RequestError::BadRequest(_) | RequestError::Incomplete | RequestError::InvalidBody { .. } => 400,
+            RequestError::UriTooLong => 414,
+            RequestError::HeadersTooLarge => 431,
+            RequestError::PayloadTooLarge => 413,
+            RequestError::NotImplemented(_) => 501,
+            RequestError::UnsupportedMediaType(_) => 415,
+            RequestError::Timeout => 408,
+        }
+    }
+
+    //what went wrong, as told to the client
+    pub fn detail(&self) -> String {
+        match self {
+            RequestError::BadRequest(reason) => format!("Bad Request: {}", reason),
+            RequestError::Incomplete => "Bad Request: incomplete request".to_string(),
+            RequestError::UriTooLong => "URI Too Long".to_string(),
+            RequestError::HeadersTooLarge => "Request Header Fields Too Large".to_string(),
+            RequestError::PayloadTooLarge => "Payload Too Large".to_string(),
+            RequestError::NotImplemented(what) => format!("Not Implemented: {}", what),
+            RequestError::UnsupportedMediaType(reason) => format!("Unsupported Media Type: {}", reason),
+            RequestError::InvalidBody { reason, .. } => format!("Bad Request: {}", reason),
+            RequestError::Timeout => "Request Timeout".to_string(),
+        }
+    }
+
+    pub fn response(&self) -> Response {
+        //clients sending structured bodies get the error in json
+        if let RequestError::InvalidBody { reason, line, column } = self {
+            let body = serde_json::json!({
+                "error": "Bad Request",
+                "detail": reason,
+                "line": line,
+                "column": column,
+            });
+            return Response::new(self.code(), "application/json", body.to_string());
+        }
+        Response::new(self.code(), "text/plain; charset=utf-8", self.detail())
+    }
+}
+
+//the request line and the headers of a request
+pub struct RequestHead {
+    pub method: RequestMethod,
+    pub path: String,
+    pub http_version: String,
+    pub headers: Headers,
+    //the number of bytes taken by the head, the body starts right after it
+    pub length: usize,
+    pub content_length: usize,
+    //the body comes in chunks, its length is only known once it is read
+    pub chunked: bool,
+}
+
+impl RequestHead {
+    //the decoded path of the target, without the query string, the one the routes match
+    pub fn route_path(&self) -> String {
+        let path = self.path.split_once('?').map(|(path, _)| path).unwrap_or(&self.path);
+        percent_encoding::percent_decode_str(path).decode_utf8_lossy().to_string()
+    }
+
+    pub fn parse(raw: &[u8]) -> Result<Self, RequestError> {
+        let mut raw_headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut raw_headers);
+        let length = match request.parse(raw) {
+            Ok(httparse::Status::Complete(length)) => length,
+            Ok(httparse::Status::Partial) => return Err(RequestError::Incomplete),
+            Err(httparse::Error::TooManyHeaders) => return Err(RequestError::HeadersTooLarge),
+            Err(err) => return Err(RequestError::BadRequest(err.to_string())),
+        };
+
+        let path = request.path.unwrap_or_default();
+        if path.len() > MAX_URI_LENGTH {
+            return Err(RequestError::UriTooLong);
+        }
+        let method = match request.method.unwrap_or_default() {
+            "GET" => RequestMethod::GET,
+            "POST" => RequestMethod::POST,
+            "DELETE" => RequestMethod::DELETE,
+            "PATCH" => RequestMethod::PATCH,
+            "PUT" => RequestMethod::PUT,
+            "HEAD" => RequestMethod::HEAD,
+            "OPTIONS" => RequestMethod::OPTIONS,
+            other => return Err(RequestError::NotImplemented(format!("method {}", other))),
+        };
+        let http_version = match request.version {
+            Some(0) => "HTTP/1.0",
+            _ => "HTTP/1.1",
+        };
+
+        let mut headers = Headers::new();
+        for header in request.headers.iter() {
+            headers.insert(header.name, &String::from_utf8_lossy(header.value));
+        }
+
+        if http_version == "HTTP/1.1" && !headers.contains("Host") {
+            return Err(RequestError::BadRequest("missing Host header".to_string()));
+        }
+        let mut chunked = false;
+        if headers.contains("Transfer-Encoding") {
+            if headers.contains("Content-Length") {
+                return Err(RequestError::BadRequest(
+                    "both Content-Length and Transfer-Encoding are set".to_string(),
+                ));
+            }
+            //chunked is the only coding we decode, and it has to be the last one applied
+            let codings: Vec<String> = headers
+                .get_all("Transfer-Encoding")
+                .iter()
+                .flat_map(|value| value.split(','))
+                .map(|coding| coding.trim().to_ascii_lowercase())
+                .filter(|coding| !coding.is_empty())
+                .collect();
+            match codings.iter().position(|coding| coding != "chunked") {
+                Some(idx) => return Err(RequestError::NotImplemented(format!("transfer encoding {}", codings[idx]))),
+                None if codings.len() != 1 => {
+                    return Err(RequestError::BadRequest("invalid Transfer-Encoding".to_string()))
+                }
+                None => chunked = true,
+            }
+        }
+
+        //repeated content lengths are only valid when they all agree
+        let mut content_length = None;
+        for value in headers.get_all("Content-Length") {
+            let value: usize = value
+                .trim()
+                .parse()
+                .map_err(|_| RequestError::BadRequest("invalid Content-Length".to_string()))?;
+            if content_length.map(|length| length != value).unwrap_or(false) {
+                return Err(RequestError::BadRequest("conflicting Content-Length".to_string()));
+            }
+            content_length = Some(value);
+        }
+        let content_length = content_length.unwrap_or(0);
+
+        Ok(Self {
+            method,
+            path: path.to_string(),
+            http_version: http_version.to_string(),
+            headers,
+            length,
+            content_length,
+            chunked,
+        })
+    }
+
+    //the head with the length of the decoded body instead of its transfer encoding, so the
+    //request reads like any other one
+    fn with_content_length(&self, content_length: usize) -> Vec<u8> {
+        let mut head = format!("{:?} {} {}\r\n", self.method, self.path, self.http_version).into_bytes();
+        for (name, value) in self.headers.iter() {
+            if name.eq_ignore_ascii_case("Transfer-Encoding") {
+                continue;
+            }
+            head.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        head.extend_from_slice(format!("Content-Length: {}\r\n\r\n", content_length).as_bytes());
+        head
+    }
+}
+
+//how big the bodies can be
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    pub max_body_size: usize,
+    //multipart bodies carry the uploads, they have their own limit
+    pub max_upload_size: usize,
+    //bigger multipart bodies, and uploaded files, are written to temporary files
+    pub upload_memory_size: usize,
+}
+
+impl BodyLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_body_size: config.max_body_size,
+            max_upload_size: config.max_upload_size,
+            upload_memory_size: config.upload_memory_size,
+        }
+    }
+}
+
+//how long the clients have to send their requests
+#[derive(Debug, Clone, Copy)]
+pub struct ReadTimeouts {
+    //for the whole head
+    pub header: Duration,
+    //between two reads of the body
+    pub body: Duration,
+}
+
+impl ReadTimeouts {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            header: Duration::from_secs(config.header_read_timeout.max(1)),
+            body: Duration::from_secs(config.body_read_timeout.max(1)),
+        }
+    }
+}
+
+//a request as read from the connection
+#[derive(Debug, Default)]
+pub struct RawRequest {
+    //the head and, unless it was spooled, the body
+    pub bytes: Vec<u8>,
+    //big multipart bodies are written to a temporary file while they are read
+    pub spooled_body: Option<File>,
+    //the address of the client, set once the request is read
+    pub peer_addr: Option<SocketAddr>,
+    //whether it came over tls
+    pub secure: bool,
+    //the certificate the client authenticated with, in der
+    pub client_certificate: Option<Vec<u8>>,
+    //whether the client wants to send another request on the connection after this one
+    pub keep_alive: bool,
+    //the head was rebuilt from an http/2 request
+    pub http2: bool,
+    //the client speaks http/1.0, it does not understand the chunked transfer encoding
+    pub http1_0: bool,
+}
+
+//reads a whole request from the stream, the head first and then the body announced by it.
+//`buffer` has the bytes already received, what comes after the request is left in it
+pub async fn read_request<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    limits: &BodyLimits,
+    timeouts: &ReadTimeouts,
+) -> Result<RawRequest, RequestError> {
+    let head_deadline = Instant::now() + timeouts.header;
+    let head = loop {
+        let head_end = find(buffer, b"\r\n\r\n");
+        if head_end.map(|end| end + 4 <= MAX_HEAD_SIZE).unwrap_or(false) {
+            break RequestHead::parse(buffer)?;
+        }
+        if buffer.len() > MAX_HEAD_SIZE {
+            //the request line is not over yet, so the target is the one too long
+            return Err(match find(&buffer[..MAX_HEAD_SIZE], b"\r\n") {
+                Some(_) => RequestError::HeadersTooLarge,
+                None => RequestError::UriTooLong,
+            });
+        }
+        match tokio::time::timeout_at(head_deadline, read_more(stream, buffer)).await {
+            Ok(read) => read?,
+            Err(_) => return Err(RequestError::Timeout),
+        }
+    };
+
+    let is_multipart = head
+        .headers
+        .get("Content-Type")
+        .and_then(|content_type| MediaType::parse(content_type))
+        .map(|media_type| media_type.is_multipart())
+        .unwrap_or(false);
+    let max_size = if is_multipart { limits.max_upload_size } else { limits.max_body_size };
+    if head.content_length > max_size {
+        return Err(RequestError::PayloadTooLarge);
+    }
+    //http/1.1 connections stay open unless the client says otherwise
+    let keep_alive = head.http_version == "HTTP/1.1"
+        && !head
+            .headers
+            .get_all("Connection")
+            .iter()
+            .any(|connection| connection.split(',').any(|token| token.trim().eq_ignore_ascii_case("close")));
+    let http1_0 = head.http_version == "HTTP/1.0";
+
+    if head.chunked {
+        let (length, bytes, spooled_body) = read_chunked_body(stream, buffer, &head, limits, is_multipart, timeouts.body).await?;
+        buffer.drain(..head.length);
+        let mut raw = head.with_content_length(length);
+        raw.extend_from_slice(&bytes);
+        return Ok(RawRequest {
+            bytes: raw,
+            spooled_body,
+            keep_alive,
+            http1_0,
+            ..Default::default()
+        });
+    }
+
+    if is_multipart && head.content_length > limits.upload_memory_size {
+        let spooled_body = spool_body(stream, buffer, &head, timeouts.body).await?;
+        return Ok(RawRequest {
+            bytes: buffer.drain(..head.length).collect(),
+            spooled_body: Some(spooled_body),
+            keep_alive,
+            http1_0,
+            ..Default::default()
+        });
+    }
+
+    let total_length = head.length + head.content_length;
+    while buffer.len() < total_length {
+        read_more_within(stream, buffer, timeouts.body).await?;
+    }
+    Ok(RawRequest {
+        bytes: buffer.drain(..total_length).collect(),
+        keep_alive,
+        http1_0,
+        ..Default::default()
+    })
+}
+
+async fn read_more<R: AsyncRead + Unpin>(stream: &mut R, buffer: &mut Vec<u8>) -> Result<(), RequestError> {
+    match stream.read_buf(buffer).await {
+        Ok(0) | Err(_) => Err(RequestError::Incomplete),
+        Ok(_) => Ok(()),
+    }
+}
+
+//writes the body to a temporary file, removed as soon as it is closed. the body is taken out of
+//`buffer`, the head and what comes after the body are left in it
+async fn spool_body<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    head: &RequestHead,
+    timeout: Duration,
+) -> Result<File, RequestError> {
+    let storage_error = |_| RequestError::BadRequest("could not store the body".to_string());
+    let mut file = tempfile::tempfile().map_err(storage_error)?;
+    let received = buffer.len().min(head.length + head.content_length);
+    file.write_all(&buffer[head.length..received]).map_err(storage_error)?;
+    buffer.drain(head.length..received);
+
+    let mut remaining = head.length + head.content_length - received;
+    let mut chunk = vec![0; 64 * 1024];
+    while remaining > 0 {
+        let to_read = remaining.min(chunk.len());
+        let bytes_read = match tokio::time::timeout(timeout, stream.read(&mut chunk[..to_read])).await {
+            Ok(read) => read.map_err(|_| RequestError::Incomplete)?,
+            Err(_) => return Err(RequestError::Timeout),
+        };
+        if bytes_read == 0 {
+            return Err(RequestError::Incomplete);
+        }
+        file.write_all(&chunk[..bytes_read]).map_err(storage_error)?;
+        remaining -= bytes_read;
+    }
+    file.seek(SeekFrom::Start(0)).map_err(storage_error)?;
+    Ok(file)
+}
+
+//decodes a chunked body, under the same limits as the others. the decoded bytes are taken out
+//of `buffer` as they come, the head and what comes after the body are left in it. returns the
+//length of the body, and the body itself in memory or in a temporary file
+async fn read_chunked_body<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    head: &RequestHead,
+    limits: &BodyLimits,
+    is_multipart: bool,
+    timeout: Duration,
+) -> Result<(usize, Vec<u8>, Option<File>), RequestError> {
+    let storage_error = |_| RequestError::BadRequest("could not store the body".to_string());
+    let max_size = if is_multipart { limits.max_upload_size } else { limits.max_body_size };
+    let start = head.length;
+    let mut bytes = Vec::new();
+    let mut spooled_body: Option<File> = None;
+    let mut length: usize = 0;
+    loop {
+        let line = read_line(stream, buffer, start, timeout).await?;
+        let size = chunk_size(&line)?;
+        if size == 0 {
+            break;
+        }
+        length = match length.checked_add(size) {
+            Some(length) if length <= max_size => length,
+            _ => return Err(RequestError::PayloadTooLarge),
+        };
+        let mut remaining = size;
+        while remaining > 0 {
+            if buffer.len() == start {
+                read_more_within(stream, buffer, timeout).await?;
+            }
+            let available = (buffer.len() - start).min(remaining);
+            //big multipart bodies are written to a temporary file, like the ones with a length
+            if is_multipart && spooled_body.is_none() && bytes.len() + available > limits.upload_memory_size {
+                let mut file = tempfile::tempfile().map_err(storage_error)?;
+                file.write_all(&bytes).map_err(storage_error)?;
+                bytes.clear();
+                spooled_body = Some(file);
+            }
+            match &mut spooled_body {
+                Some(file) => file.write_all(&buffer[start..start + available]).map_err(storage_error)?,
+                None => bytes.extend_from_slice(&buffer[start..start + available]),
+            }
+            buffer.drain(start..start + available);
+            remaining -= available;
+        }
+        if !read_line(stream, buffer, start, timeout).await?.is_empty() {
+            return Err(RequestError::BadRequest("invalid chunk".to_string()));
+        }
+    }
+    //the trailer fields are read to find the end of the request, but not kept
+    let mut trailers_size = 0;
+    loop {
+        let line = read_line(stream, buffer, start, timeout).await?;
+        if line.is_empty() {
+            break;
+        }
+        trailers_size += line.len() + 2;
+        if trailers_size > MAX_HEAD_SIZE {
+            return Err(RequestError::HeadersTooLarge);
+        }
+    }
+    if let Some(file) = &mut spooled_body {
+        file.seek(SeekFrom::Start(0)).map_err(storage_error)?;
+    }
+    Ok((length, bytes, spooled_body))
+}
+
+//takes the next line out of `buffer` from `start`, without its line break
+async fn read_line<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    start: usize,
+    timeout: Duration,
+) -> Result<Vec<u8>, RequestError> {
+    loop {
+        if let Some(end) = find(&buffer[start..], b"\r\n") {
+            let mut line: Vec<u8> = buffer.drain(start..start + end + 2).collect();
+            line.truncate(end);
+            return Ok(line);
+        }
+        if buffer.len() - start > MAX_CHUNK_LINE {
+            return Err(RequestError::BadRequest("chunk line too long".to_string()));
+        }
+        read_more_within(stream, buffer, timeout).await?;
+    }
+}
+
+//the size announced by a chunk line, in hexadecimal and maybe followed by extensions
+fn chunk_size(line: &[u8]) -> Result<usize, RequestError> {
+    let invalid = || RequestError::BadRequest("invalid chunk size".to_string());
+    let size = line.split(|byte| *byte == b';').next().unwrap_or_default();
+    let size = std::str::from_utf8(size).map_err(|_| invalid())?.trim_end_matches([' ', '\t']);
+    if size.is_empty() || size.len() > 16 || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    usize::from_str_radix(size, 16).map_err(|_| invalid())
+}
+
+async fn read_more_within<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    timeout: Duration,
+) -> Result<(), RequestError> {
+    match tokio::time::timeout(timeout, read_more(stream, buffer)).await {
+        Ok(read) => read,
+        Err(_) => Err(RequestError::Timeout),
+    }
+}
+
+pub(crate) fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::quickcheck;
+
+    const LIMITS: BodyLimits = BodyLimits {
+        max_body_size: 1024,
+        max_upload_size: 4096,
+        upload_memory_size: 512,
+    };
+    const TIMEOUTS: ReadTimeouts = ReadTimeouts {
+        header: Duration::from_secs(1),
+        body: Duration::from_secs(1),
+    };
+
+    fn parse(raw: &str) -> Result<RequestHead, RequestError> {
+        RequestHead::parse(raw.as_bytes())
+    }
+
+    fn code(result: Result<RequestHead, RequestError>) -> i32 {
+        result.err().map(|err| err.code()).unwrap_or(200)
+    }
+
+    //reads from the bytes as if they came from a connection, returns the request and what is left
+    fn read(raw: &[u8]) -> (Result<RawRequest, RequestError>, Vec<u8>) {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let mut stream = raw;
+        let mut buffer = Vec::new();
+        let request = runtime.block_on(read_request(&mut stream, &mut buffer, &LIMITS, &TIMEOUTS));
+        buffer.extend_from_slice(stream);
+        (request, buffer)
+    }
+
+    #[test]
+    fn parses_a_request() {
+        let head = parse("POST /items?id=1 HTTP/1.1\r\nHost: example.com\r\nContent-Length: 4\r\n\r\nbody").unwrap();
+        assert!(matches!(head.method, RequestMethod::POST));
+        assert_eq!(head.path, "/items?id=1");
+        assert_eq!(head.http_version, "HTTP/1.1");
+        assert_eq!(head.headers.get("host").map(String::as_str), Some("example.com"));
+        assert_eq!(head.content_length, 4);
+        assert_eq!(head.length, 67);
+    }
+
+    #[test]
+    fn refuses_a_bad_request_line() {
+        assert_eq!(code(parse("GET\r\nHost: x\r\n\r\n")), 400);
+        assert_eq!(code(parse("GET / HTTP/9.9\r\nHost: x\r\n\r\n")), 400);
+        assert_eq!(code(parse("GET  / HTTP/1.1\r\nHost: x\r\n\r\n")), 400);
+    }
+
+    #[test]
+    fn refuses_a_header_without_colon() {
+        assert_eq!(code(parse("GET / HTTP/1.1\r\nHost: x\r\nno colon here\r\n\r\n")), 400);
+    }
+
+    #[test]
+    fn refuses_a_missing_host_on_http_1_1_only() {
+        assert_eq!(code(parse("GET / HTTP/1.1\r\n\r\n")), 400);
+        assert_eq!(code(parse("GET / HTTP/1.0\r\n\r\n")), 200);
+    }
+
+    #[test]
+    fn refuses_a_long_uri() {
+        let target = format!("/{}", "a".repeat(MAX_URI_LENGTH));
+        assert_eq!(code(parse(&format!("GET {} HTTP/1.1\r\nHost: x\r\n\r\n", target))), 414);
+        //a request line that does not even fit in the head
+        let target = format!("/{}", "a".repeat(MAX_HEAD_SIZE));
+        let (request, _) = read(format!("GET {} HTTP/1.1\r\nHost: x\r\n\r\n", target).as_bytes());
+        assert_eq!(request.unwrap_err(), RequestError::UriTooLong);
+    }
+
+    #[test]
+    fn refuses_a_big_head() {
+        let raw = format!("GET / HTTP/1.1\r\nHost: x\r\nX-Big: {}\r\n\r\n", "a".repeat(MAX_HEAD_SIZE));
+        let (request, _) = read(raw.as_bytes());
+        assert_eq!(request.unwrap_err(), RequestError::HeadersTooLarge);
+    }
+
+    #[test]
+    fn refuses_too_many_headers() {
+        let headers: String = (0..MAX_HEADERS).map(|idx| format!("X-Header-{}: {}\r\n", idx, idx)).collect();
+        assert_eq!(code(parse(&format!("GET / HTTP/1.1\r\nHost: x\r\n{}\r\n", headers))), 431);
+    }
+
+    #[test]
+    fn does_not_implement_trace_and_connect() {
+        assert_eq!(code(parse("TRACE / HTTP/1.1\r\nHost: x\r\n\r\n")), 501);
+        assert_eq!(code(parse("CONNECT example.com:443 HTTP/1.1\r\nHost: example.com\r\n\r\n")), 501);
+    }
+
+    #[test]
+    fn refuses_ambiguous_lengths() {
+        assert_eq!(code(parse("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n")), 400);
+        assert_eq!(code(parse("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: -1\r\n\r\n")), 400);
+        let raw = "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(code(parse(raw)), 400);
+    }
+
+    #[test]
+    fn only_decodes_chunked() {
+        assert!(parse("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: Chunked\r\n\r\n").unwrap().chunked);
+        assert_eq!(code(parse("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: gzip, chunked\r\n\r\n")), 501);
+        assert_eq!(code(parse("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked, chunked\r\n\r\n")), 400);
+    }
+
+    #[test]
+    fn reads_the_body_and_leaves_the_next_request() {
+        let (request, rest) = read(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n");
+        let request = request.unwrap();
+        assert!(request.bytes.ends_with(b"\r\n\r\nhello"));
+        assert!(request.keep_alive);
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let raw = b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5;name=value\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\nNEXT";
+        let (request, rest) = read(raw);
+        let request = request.unwrap();
+        let head = RequestHead::parse(&request.bytes).unwrap();
+        assert!(!head.chunked);
+        assert!(!head.headers.contains("Transfer-Encoding"));
+        assert_eq!(head.content_length, 11);
+        assert_eq!(&request.bytes[head.length..], b"hello world");
+        assert_eq!(rest, b"NEXT");
+    }
+
+    #[test]
+    fn refuses_bad_chunks() {
+        let head = "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let read_chunks = |chunks: &str| read(format!("{}{}", head, chunks).as_bytes()).0.unwrap_err();
+        assert_eq!(read_chunks("zz\r\n").code(), 400);
+        assert_eq!(read_chunks("3\r\nabcX\r\n0\r\n\r\n").code(), 400);
+        assert_eq!(read_chunks("ffffffffffffffff\r\n"), RequestError::PayloadTooLarge);
+        assert_eq!(read_chunks(&format!("{:x}\r\n", LIMITS.max_body_size + 1)), RequestError::PayloadTooLarge);
+        assert_eq!(read_chunks("5\r\nhel"), RequestError::Incomplete);
+    }
+
+    #[test]
+    fn refuses_big_bodies() {
+        let raw = format!("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n", LIMITS.max_body_size + 1);
+        assert_eq!(read(raw.as_bytes()).0.unwrap_err(), RequestError::PayloadTooLarge);
+    }
+
+    #[test]
+    fn spools_big_multipart_bodies() {
+        let body = "a".repeat(LIMITS.upload_memory_size + 1);
+        let raw = format!(
+            "POST / HTTP/1.1\r\nHost: x\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let (request, _) = read(raw.as_bytes());
+        assert!(request.unwrap().spooled_body.is_some());
+    }
+
+    #[test]
+    fn refuses_incomplete_requests() {
+        assert_eq!(read(b"GET / HTTP/1.1\r\nHost:").0.unwrap_err(), RequestError::Incomplete);
+        assert_eq!(read(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 9\r\n\r\nshort").0.unwrap_err(), RequestError::Incomplete);
+    }
+
+    quickcheck! {
+        //the parser answers with an error, never with a panic
+        fn parsing_never_panics(raw: Vec<u8>) -> bool {
+            let _ = RequestHead::parse(&raw);
+            true
+        }
+
+        //same for the bytes following a valid start, which get further into the parser
+        fn reading_never_panics(rest: Vec<u8>, chunked: bool) -> bool {
+            let start: &[u8] = match chunked {
+                true => b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n",
+                false => b"POST / HTTP/1.1\r\nHost: x\r\n",
+            };
+            let _ = read(&[start, &rest].concat());
+            true
+        }
+
+        fn chunk_sizes_never_panic(line: Vec<u8>) -> bool {
+            let _ = chunk_size(&line);
+            true
+        }
+    }
+}
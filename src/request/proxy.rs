@@ -0,0 +1,274 @@
+extern crate ipnet;
+
+use crate::request::Headers;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+
+//the reverse proxies whose forwarding headers are honored, from the `trusted_proxies` of the config
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    //every entry is a cidr like `10.0.0.0/8`, or a single address
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let mut networks = Vec::new();
+        for entry in entries {
+            let network = match entry.parse::<IpNet>() {
+                Ok(network) => network,
+                Err(_) => match entry.parse::<IpAddr>() {
+                    Ok(addr) => IpNet::from(addr),
+                    Err(_) => return Err(format!("invalid trusted proxy {}", entry)),
+                },
+            };
+            networks.push(network);
+        }
+        Ok(Self { networks })
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        //ipv4 clients can reach ipv6 sockets as mapped addresses
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*addr),
+            IpAddr::V4(_) => *addr,
+        };
+        self.networks.iter().any(|network| network.contains(&addr))
+    }
+}
+
+//who sent the request and how, once the proxies in front of us are accounted for
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub scheme: String,
+    pub host: String,
+}
+
+//a hop of the forwarding chain, as told by a proxy
+struct Hop {
+    ip: Option<IpAddr>,
+    port: Option<u16>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+impl ClientInfo {
+    //the peer is the client, unless it is a trusted proxy: then the forwarding headers are
+    //followed from the closest hop, until an address that is not a trusted proxy
+    pub fn resolve(peer: Option<SocketAddr>, secure: bool, headers: &Headers, proxies: &TrustedProxies) -> Self {
+        let mut client = Self {
+            ip: peer.map(|peer| peer.ip()),
+            port: peer.map(|peer| peer.port()),
+            scheme: if secure { "https" } else { "http" }.to_string(),
+            host: headers.get("Host").cloned().unwrap_or_default(),
+        };
+        let trusted = |ip: &Option<IpAddr>| ip.map(|ip| proxies.contains(&ip)).unwrap_or(false);
+        if !trusted(&client.ip) {
+            return client;
+        }
+
+        //the `Forwarded` header is the standard one, the `X-Forwarded-*` are only read without it
+        let hops = match headers.contains("Forwarded") {
+            true => forwarded_hops(headers),
+            false => x_forwarded_hops(headers),
+        };
+        for hop in hops.into_iter().rev() {
+            if let Some(proto) = hop.proto {
+                client.scheme = proto;
+            }
+            if let Some(host) = hop.host {
+                client.host = host;
+            }
+            //an obfuscated or unknown address ends the chain
+            if hop.ip.is_none() {
+                break;
+            }
+            client.ip = hop.ip;
+            client.port = hop.port;
+            if !trusted(&client.ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+//the elements of the `Forwarded` headers, like `for=192.0.2.60;proto=https;by=203.0.113.43`
+fn forwarded_hops(headers: &Headers) -> Vec<Hop> {
+    let mut hops = Vec::new();
+    for header in headers.get_all("Forwarded") {
+        for element in header.split(',') {
+            let mut hop = Hop {
+                ip: None,
+                port: None,
+                proto: None,
+                host: None,
+            };
+            for pair in element.split(';') {
+                let (key, value) = match pair.split_once('=') {
+                    Some(pair) => pair,
+                    None => continue,
+                };
+                let value = value.trim().trim_matches('"');
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => (hop.ip, hop.port) = parse_node(value),
+                    "proto" => hop.proto = parse_proto(value),
+                    "host" if !value.is_empty() => hop.host = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            hops.push(hop);
+        }
+    }
+    hops
+}
+
+//`X-Forwarded-For` lists the addresses, `X-Forwarded-Proto` the scheme the client used
+fn x_forwarded_hops(headers: &Headers) -> Vec<Hop> {
+    let mut hops: Vec<Hop> = headers
+        .get_all("X-Forwarded-For")
+        .into_iter()
+        .flat_map(|header| header.split(','))
+        .map(|node| {
+            let (ip, port) = parse_node(node.trim());
+            Hop {
+                ip,
+                port,
+                proto: None,
+                host: None,
+            }
+        })
+        .collect();
+    //every proxy appends the scheme it was reached with next to the address it saw,
+    //so both lists are matched from the closest proxy
+    let protos: Vec<&str> = headers
+        .get_all("X-Forwarded-Proto")
+        .into_iter()
+        .flat_map(|header| header.split(','))
+        .collect();
+    for (hop, proto) in hops.iter_mut().rev().zip(protos.into_iter().rev()) {
+        hop.proto = parse_proto(proto.trim());
+    }
+    hops
+}
+
+//a node like `192.0.2.43`, `192.0.2.43:47011`, `[2001:db8::1]:4711` or `2001:db8::1`
+fn parse_node(node: &str) -> (Option<IpAddr>, Option<u16>) {
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return (Some(addr.ip()), Some(addr.port()));
+    }
+    match node.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => (Some(ip), None),
+        Err(_) => (None, None),
+    }
+}
+
+fn parse_proto(proto: &str) -> Option<String> {
+    match proto.to_ascii_lowercase().as_str() {
+        "http" => Some("http".to_string()),
+        "https" => Some("https".to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::parse(&["10.0.0.0/8".to_string(), "::1".to_string()]).unwrap()
+    }
+
+    fn resolve(peer: &str, headers: &[(&str, &str)]) -> ClientInfo {
+        let mut request_headers = Headers::new();
+        request_headers.insert("Host", "internal:8000");
+        for (name, value) in headers {
+            request_headers.insert(name, value);
+        }
+        ClientInfo::resolve(Some(peer.parse().unwrap()), false, &request_headers, &proxies())
+    }
+
+    fn ip(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn ignores_the_headers_of_untrusted_peers() {
+        let client = resolve(
+            "203.0.113.9:5000",
+            &[
+                ("X-Forwarded-For", "1.2.3.4"),
+                ("X-Forwarded-Proto", "https"),
+                ("Forwarded", "for=1.2.3.4;proto=https;host=example.com"),
+            ],
+        );
+        assert_eq!(client.ip, ip("203.0.113.9"));
+        assert_eq!(client.port, Some(5000));
+        assert_eq!(client.scheme, "http");
+        assert_eq!(client.host, "internal:8000");
+    }
+
+    #[test]
+    fn stops_at_the_first_untrusted_hop() {
+        //the client made up the first address, the proxy appended the one it saw
+        let client = resolve("10.0.0.1:5000", &[("X-Forwarded-For", "6.6.6.6, 198.51.100.7, 10.0.0.2")]);
+        assert_eq!(client.ip, ip("198.51.100.7"));
+        assert_eq!(client.port, None);
+        let client = resolve("10.0.0.1:5000", &[("X-Forwarded-For", "6.6.6.6"), ("X-Forwarded-For", "198.51.100.7")]);
+        assert_eq!(client.ip, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn matches_the_schemes_from_the_closest_proxy() {
+        let client = resolve(
+            "10.0.0.1:5000",
+            &[("X-Forwarded-For", "198.51.100.7, 10.0.0.2"), ("X-Forwarded-Proto", "https, http")],
+        );
+        assert_eq!(client.ip, ip("198.51.100.7"));
+        assert_eq!(client.scheme, "https");
+        //an unknown scheme is not taken
+        let client = resolve("10.0.0.1:5000", &[("X-Forwarded-For", "198.51.100.7"), ("X-Forwarded-Proto", "gopher")]);
+        assert_eq!(client.scheme, "http");
+    }
+
+    #[test]
+    fn prefers_the_forwarded_header() {
+        let client = resolve(
+            "10.0.0.1:5000",
+            &[
+                ("X-Forwarded-For", "6.6.6.6"),
+                ("Forwarded", r#"for="[2001:db8::7]:4711";proto=https;host=example.com, for=10.0.0.2"#),
+            ],
+        );
+        assert_eq!(client.ip, ip("2001:db8::7"));
+        assert_eq!(client.port, Some(4711));
+        assert_eq!(client.scheme, "https");
+        assert_eq!(client.host, "example.com");
+    }
+
+    #[test]
+    fn keeps_the_proxy_when_the_client_is_hidden() {
+        let client = resolve("10.0.0.1:5000", &[("Forwarded", "for=_hidden;proto=https")]);
+        assert_eq!(client.ip, ip("10.0.0.1"));
+        assert_eq!(client.scheme, "https");
+        let client = resolve("10.0.0.1:5000", &[("X-Forwarded-For", "not an address")]);
+        assert_eq!(client.ip, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn trusts_ipv4_proxies_reached_over_ipv6() {
+        let client = resolve("[::ffff:10.0.0.1]:5000", &[("X-Forwarded-For", "198.51.100.7")]);
+        assert_eq!(client.ip, ip("198.51.100.7"));
+        let client = resolve("[::1]:5000", &[("X-Forwarded-For", "198.51.100.7")]);
+        assert_eq!(client.ip, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn refuses_invalid_proxies() {
+        assert!(TrustedProxies::parse(&["10.0.0.0/33".to_string()]).is_err());
+        assert!(TrustedProxies::parse(&["proxy.local".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+extern crate cookie;
+extern crate pyo3;
+
+use crate::request::{parse_cookies, BodyLimits, Cookies, Request, RequestError};
+use cookie::Key;
+use pyo3::exceptions::{PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+use pythonize::pythonize;
+use std::collections::HashMap;
+
+//raised when a handler reads a body that can not be parsed, if the handler lets it
+//go the client gets the error of the body instead of a 500
+pyo3::create_exception!(fastry, BadRequest, PyValueError);
+
+//what python handlers get as the request. the body is only parsed, and every property
+//only converted to python, the first time the handler reads it
+#[pyclass(name = "Request")]
+pub struct PyRequest {
+    request: Request,
+    limits: BodyLimits,
+    cookie_key: Option<Key>,
+    //the properties already converted to python, by name
+    cache: HashMap<&'static str, PyObject>,
+    //why the body could not be parsed, once the handler tried to read it
+    body_error: Option<RequestError>,
+}
+
+impl PyRequest {
+    pub fn new(request: Request, limits: BodyLimits, cookie_key: Option<Key>) -> Self {
+        Self {
+            request,
+            limits,
+            cookie_key,
+            cache: HashMap::new(),
+            body_error: None,
+        }
+    }
+
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    pub fn body_error(&self) -> Option<&RequestError> {
+        self.body_error.as_ref()
+    }
+
+    //the property from the cache, built the first time
+    fn cached<F>(&mut self, py: Python, name: &'static str, build: F) -> PyResult<PyObject>
+    where
+        F: FnOnce(&mut Self, Python) -> PyResult<PyObject>,
+    {
+        if let Some(value) = self.cache.get(name) {
+            return Ok(value.clone_ref(py));
+        }
+        let value = build(self, py)?;
+        self.cache.insert(name, value.clone_ref(py));
+        Ok(value)
+    }
+
+    //keeps the error to answer with it, and raises it to the handler
+    fn fail(&mut self, err: RequestError) -> PyErr {
+        let py_err = BadRequest::new_err(err.detail());
+        self.body_error = Some(err);
+        py_err
+    }
+
+    //parses the body for its content type, once
+    fn process(&mut self) -> PyResult<()> {
+        if let Some(err) = &self.body_error {
+            return Err(BadRequest::new_err(err.detail()));
+        }
+        match self.request.process(&self.limits) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.fail(err)),
+        }
+    }
+}
+
+#[pymethods]
+impl PyRequest {
+    #[getter]
+    fn method(&self) -> String {
+        format!("{:?}", self.request.method)
+    }
+
+    #[getter]
+    fn path(&self) -> String {
+        self.request.path.clone()
+    }
+
+    #[getter]
+    fn http_version(&self) -> String {
+        self.request.http_version.clone()
+    }
+
+    //`http` or `https`, as seen by the client
+    #[getter]
+    fn scheme(&self) -> String {
+        self.request.client.scheme.clone()
+    }
+
+    //the host the client asked for, with its port if it gave one
+    #[getter]
+    fn host(&self) -> String {
+        self.request.client.host.clone()
+    }
+
+    //the url requested by the client, rebuilt from the scheme, the host and the request target
+    #[getter]
+    fn url(&self) -> String {
+        format!("{}://{}{}", self.request.client.scheme, self.request.client.host, self.request.target)
+    }
+
+    //the ip and port of the client, the port is None when a proxy did not forward it
+    #[getter]
+    fn client_addr(&self) -> Option<(String, Option<u16>)> {
+        let client = &self.request.client;
+        client.ip.map(|ip| (ip.to_string(), client.port))
+    }
+
+    //the ip and port of the connection, the proxy when there is one
+    #[getter]
+    fn peer_addr(&self) -> Option<(String, u16)> {
+        self.request.peer_addr.map(|addr| (addr.ip().to_string(), addr.port()))
+    }
+
+    //the der certificate the client authenticated with over mutual tls
+    #[getter]
+    fn client_certificate(&self, py: Python) -> Option<PyObject> {
+        let cert = self.request.client_certificate.as_ref()?;
+        Some(PyBytes::new(py, cert).into_py(py))
+    }
+
+    //the id of the last event a reconnecting event stream client got
+    #[getter]
+    fn last_event_id(&self) -> Option<String> {
+        self.request.headers.get("Last-Event-ID").cloned()
+    }
+
+    #[getter]
+    fn path_variables(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "path_variables", |this, py| {
+            Ok(this.request.path_variables.clone().unwrap_or_default().into_py(py))
+        })
+    }
+
+    #[getter]
+    fn headers(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "headers", |this, py| Ok(Py::new(py, this.request.headers.clone())?.into_py(py)))
+    }
+
+    #[getter]
+    fn query(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "query", |this, py| Ok(Py::new(py, this.request.query.clone())?.into_py(py)))
+    }
+
+    #[getter]
+    fn cookies(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "cookies", |this, py| {
+            let cookies = parse_cookies(&this.request.headers);
+            Ok(Cookies::to_python(py, cookies, this.cookie_key.clone())?.into_py(py))
+        })
+    }
+
+    #[getter]
+    fn body(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "body", |this, py| match this.request.body() {
+            Ok(body) => Ok(PyBytes::new(py, &body).into_py(py)),
+            Err(err) => Err(this.fail(err)),
+        })
+    }
+
+    //the body decoded with the charset of the content type
+    #[getter]
+    fn text(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "text", |this, py| match this.request.text() {
+            Ok(text) => Ok(text.into_py(py)),
+            Err(err) => Err(this.fail(err)),
+        })
+    }
+
+    #[getter]
+    fn json(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "json", |this, py| {
+            this.process()?;
+            Ok(pythonize(py, &this.request.json)?)
+        })
+    }
+
+    #[getter]
+    fn xml(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "xml", |this, py| {
+            this.process()?;
+            Ok(pythonize(py, &this.request.xml)?)
+        })
+    }
+
+    #[getter]
+    fn form(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "form", |this, py| {
+            this.process()?;
+            Ok(Py::new(py, this.request.form.clone())?.into_py(py))
+        })
+    }
+
+    //a dict from every field name to the list of its files
+    #[getter]
+    fn files(&mut self, py: Python) -> PyResult<PyObject> {
+        self.cached(py, "files", |this, py| {
+            this.process()?;
+            let files = PyDict::new(py);
+            for file in &this.request.files {
+                let field_files = match files.get_item(&file.name) {
+                    Some(field_files) => field_files.downcast::<PyList>()?,
+                    None => {
+                        let field_files = PyList::empty(py);
+                        files.set_item(&file.name, field_files)?;
+                        field_files
+                    }
+                };
+                field_files.append(file.to_python(py)?)?;
+            }
+            Ok(files.into_py(py))
+        })
+    }
+
+    //handlers written for the dict the request used to be keep working
+    fn __getitem__(slf: &PyCell<Self>, key: &str) -> PyResult<PyObject> {
+        const PROPERTIES: [&str; 20] = [
+            "method",
+            "path",
+            "http_version",
+            "scheme",
+            "host",
+            "url",
+            "client_addr",
+            "peer_addr",
+            "client_certificate",
+            "last_event_id",
+            "path_variables",
+            "headers",
+            "query",
+            "cookies",
+            "body",
+            "text",
+            "json",
+            "xml",
+            "form",
+            "files",
+        ];
+        if !PROPERTIES.contains(&key) {
+            return Err(PyKeyError::new_err(key.to_string()));
+        }
+        Ok(slf.getattr(key)?.into())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Request({:?} {})", self.request.method, self.request.target)
+    }
+}
@@ -1,6 +1,14 @@
+
+use crate::request::{
+    parse_multipart, ClientInfo, TrustedProxies, parse_xml, BodyLimits, Headers, MediaType, MultiDict, RawRequest, RequestError, RequestHead,
+    UploadedFile,
+};
 use serde::Serialize;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::net::SocketAddr;
 
 #[allow(dead_code)]
 #[derive(Serialize, Debug, Clone)]
@@ -11,161 +19,155 @@ pub enum RequestMethod {
     PATCH,
     DELETE,
     HEAD,
+    OPTIONS,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Serialize, Debug)]
 pub struct Request {
     pub method: RequestMethod,
     pub http_version: String,
-    pub raw_headers: String,
-    pub raw_body: String,
+    #[serde(skip)]
+    pub raw_body: Vec<u8>,
+    //big multipart bodies are not kept in `raw_body`, but in a temporary file
+    #[serde(skip)]
+    pub spooled_body: Option<File>,
+    //the request target as received, percent encoded and with the query string
+    pub target: String,
+    //the percent decoded path, without the query string
     pub path: String,
-    pub raw_request: String,
+    pub query: MultiDict,
     pub json: Option<Value>,
-    pub headers: Option<HashMap<String, String>>,
+    pub xml: Option<Value>,
+    //the fields of url encoded and multipart forms
+    pub form: MultiDict,
+    pub files: Vec<UploadedFile>,
+    pub headers: Headers,
     pub path_variables: Option<HashMap<String, String>>,
+    //the address of the connection, a proxy when there is one
+    pub peer_addr: Option<SocketAddr>,
+    //whether the connection is over tls
+    pub secure: bool,
+    //the certificate the client authenticated with, in der
+    #[serde(skip)]
+    pub client_certificate: Option<Vec<u8>>,
+    //the client address, scheme and host, from the forwarding headers of the trusted proxies
+    pub client: ClientInfo,
+    //whether the body was already processed
+    #[serde(skip)]
+    processed: bool,
 }
 
-#[derive(Serialize)]
-pub struct ProcessedRequest {
-    pub method: RequestMethod,
-    pub http_version: String,
-    pub json: Value,
-    pub headers: HashMap<String, String>,
-    pub path_variables: HashMap<String, String>,
-    pub text: String,
-}
-
-impl ProcessedRequest {
-    pub fn from_request(mut request: Request) -> Self {
-        request.process();
-        Self {
-            method: request.method,
-            http_version: request.http_version,
-            json: request.json.unwrap_or(Value::Null),
-            headers: request.headers.unwrap(),
-            text: request.raw_body,
-            path_variables: request.path_variables.unwrap_or(HashMap::new())
+impl Request {
+    //parses a whole request, as received by `read_request`
+    pub fn parse(raw: RawRequest) -> Result<Self, RequestError> {
+        let head = RequestHead::parse(&raw.bytes)?;
+        let body_end = match raw.spooled_body {
+            Some(_) => head.length,
+            None => head.length + head.content_length,
+        };
+        if raw.bytes.len() < body_end {
+            return Err(RequestError::Incomplete);
         }
+        let path = head.route_path();
+        let query = head.path.split_once('?').map(|(_, query)| query).unwrap_or_default();
+        Ok(Self {
+            method: head.method,
+            http_version: if raw.http2 { "HTTP/2".to_string() } else { head.http_version },
+            raw_body: raw.bytes[head.length..body_end].to_vec(),
+            spooled_body: raw.spooled_body,
+            target: head.path.clone(),
+            path,
+            query: MultiDict::parse_urlencoded(query.as_bytes()),
+            json: None,
+            xml: None,
+            form: MultiDict::new(),
+            files: Vec::new(),
+            path_variables: None,
+            client: ClientInfo::resolve(raw.peer_addr, raw.secure, &head.headers, &TrustedProxies::default()),
+            peer_addr: raw.peer_addr,
+            secure: raw.secure,
+            client_certificate: raw.client_certificate,
+            headers: head.headers,
+            processed: false,
+        })
     }
-}
 
-impl Request {
-    pub fn from_string(string: String)-> Self {
-        let (method, path, http_version) = Self::get_request_core_info(&string);
-        Self {
-            method: method,
-            http_version: http_version,
-            raw_headers: Self::get_raw_headers(&string),
-            raw_body: Self::get_raw_body(&string),
-            path: path,
-            raw_request: String::new(), //as_str.to_string(),
-            json: None,
-            headers: None,
-            path_variables: None, 
-        }
+    //trusts the forwarding headers when the request comes from one of the proxies
+    pub fn resolve_client(&mut self, proxies: &TrustedProxies) {
+        self.client = ClientInfo::resolve(self.peer_addr, self.secure, &self.headers, proxies);
     }
 
-    fn get_request_core_info(request: &str) -> (RequestMethod, String, String) {
-        //the method is ussually the first thing of the request
-        let mut method_string = String::new();
-        let mut path = String::new();
-        let mut http_version = String::new();
-        let mut state: u8 = 0;
-        for ch in request.chars() {
-            if ch == '\r' {
-                break;
-            }
-            if ch == ' ' {
-                if state == 2 {
-                    break;
-                }
-                state += 1;
-                continue;
-            }
-            if state == 0 {
-                method_string.push(ch);
-            } else if state == 1 {
-                path.push(ch);
-            } else if state == 2 {
-                http_version.push(ch);
+    //the whole body, read back from the temporary file when it was spooled
+    pub fn body(&mut self) -> Result<Vec<u8>, RequestError> {
+        match &mut self.spooled_body {
+            Some(file) => {
+                let storage_error = |_| RequestError::BadRequest("could not read the body".to_string());
+                let mut body = Vec::new();
+                file.seek(SeekFrom::Start(0)).map_err(storage_error)?;
+                file.read_to_end(&mut body).map_err(storage_error)?;
+                Ok(body)
             }
+            None => Ok(self.raw_body.clone()),
         }
-        let method = match method_string.as_str() {
-            "GET" => RequestMethod::GET,
-            "POST" => RequestMethod::POST,
-            "DELETE" => RequestMethod::DELETE,
-            "PATCH" => RequestMethod::PATCH,
-            "PUT" => RequestMethod::PUT,
-            "HEAD" => RequestMethod::HEAD,
-            _ => unreachable!(),
-        };
-
-        (method, path, http_version)
-    }
-
-    //parses the raw request to get the raw raw_headers
-    fn get_raw_headers(request: &str) -> String {
-        let start_idx = request.find("\r\n").unwrap();
-        let end_idx = request.find("\r\n\r\n").unwrap();
-
-        request[start_idx + 2..end_idx].to_string()
     }
 
-    //parses the raw request to get the raw json content
-    fn get_raw_body(request: &str) -> String {
-        let start_idx = request.find("\r\n\r\n").unwrap() + 4;
-        request[start_idx..].to_string()
+    //the body decoded with the charset of the content type, invalid bytes are replaced
+    pub fn text(&mut self) -> Result<String, RequestError> {
+        let body = self.body()?;
+        match self.headers.get("Content-Type").and_then(|content_type| MediaType::parse(content_type)) {
+            Some(media_type) => Ok(media_type.decode(&body, false)?.into_owned()),
+            None => Ok(String::from_utf8_lossy(&body).to_string()),
+        }
     }
 
-    pub fn process(&mut self) {
+    pub fn process(&mut self, limits: &BodyLimits) -> Result<(), RequestError> {
         //process the request for being passed to python
         //first process the headers, and see the body type and lenght
-        let headers: HashMap<String, String>;
-
-        if self.headers.is_some() {
-            return;
-        }
-
-        headers = self.headers();
-
-        match headers
-            .get(&String::from("Content-Type"))
-            .unwrap_or(&String::from(""))
-            .as_str()
-        {
-            "application/json" => {
-                //parse the body with serde
-                let value: Value =
-                    serde_json::from_str(self.raw_body.as_str()).expect("Wrong json");
-                self.json = Some(value);
-            }
-            "application/xml" => {
-                //TODO: support xml parsing
-            }
-            _ => {}
+        if self.processed {
+            return Ok(());
         }
+        self.processed = true;
 
-        self.headers = Some(headers);
-    }
-
-    //TODO: get json and create json struct
-    //todo get the json and return it
-    //preferably do it in async way
-    //async fn json() -> () {}
-
-    //TODO: get raw_headers and create headers struct
-    //todo get the headers of the request and return it
-    //preferably do it in async way
-    pub fn headers(&self) -> HashMap<String, String> {
-        let mut headers = HashMap::<String, String>::new();
-        for line in self.raw_headers.split("\r\n") {
-            let (key, value) = line.split_once(':').unwrap();
-            let key = key.trim();
-            let value = value.trim();
-            headers.insert(key.to_string(), value.to_string());
+        let media_type = match self.headers.get("Content-Type") {
+            Some(content_type) => MediaType::parse(content_type)
+                .ok_or_else(|| RequestError::BadRequest(format!("invalid Content-Type {}", content_type)))?,
+            None => return Ok(()),
+        };
+        if media_type.is_json() {
+            //bodies with a charset other than utf-8 are decoded before parsing
+            let text = media_type.decode(&self.raw_body, true)?;
+            let value: Value = serde_json::from_str(&text).map_err(|err| RequestError::InvalidBody {
+                reason: format!("invalid json: {}", err),
+                line: Some(err.line()),
+                column: Some(err.column()),
+            })?;
+            self.json = Some(value);
+        } else if media_type.is_xml() {
+            let text = media_type.decode(&self.raw_body, true)?;
+            self.xml = Some(parse_xml(&text)?);
+        } else if media_type.essence == "application/x-www-form-urlencoded" {
+            self.form = MultiDict::parse_urlencoded(&self.raw_body);
+        } else if media_type.essence == "multipart/form-data" {
+            let boundary = media_type
+                .param("boundary")
+                .ok_or_else(|| RequestError::BadRequest("multipart body without boundary".to_string()))?;
+            self.files = match &self.spooled_body {
+                Some(file) => {
+                    //a clone shares the position, the original is kept to read the body back
+                    let storage_error = |_| RequestError::BadRequest("could not read the body".to_string());
+                    let mut file = file.try_clone().map_err(storage_error)?;
+                    file.seek(SeekFrom::Start(0)).map_err(storage_error)?;
+                    parse_multipart(file, boundary, limits.upload_memory_size, &mut self.form)?
+                }
+                None => parse_multipart(
+                    self.raw_body.as_slice(),
+                    boundary,
+                    limits.upload_memory_size,
+                    &mut self.form,
+                )?,
+            };
         }
-        headers
+        Ok(())
     }
 }
@@ -0,0 +1,132 @@
+extern crate roxmltree;
+
+use crate::request::RequestError;
+use serde_json::{Map, Value};
+
+//the deepest element nesting we accept
+const MAX_DEPTH: usize = 256;
+
+//parses an xml body to the structure given to the handlers, every element is a map like
+//`{"tag": ..., "attributes": {...}, "text": ..., "tail": ..., "children": [...]}`,
+//the text and tail following the meaning they have in python's ElementTree.
+//document type declarations are refused, so no entity is ever expanded
+pub fn parse_xml(text: &str) -> Result<Value, RequestError> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: false,
+        ..roxmltree::ParsingOptions::default()
+    };
+    let document = roxmltree::Document::parse_with_options(text, options)
+        .map_err(|err| {
+            let position = err.pos();
+            RequestError::InvalidBody {
+                reason: format!("invalid xml: {}", err),
+                line: Some(position.row as usize),
+                column: Some(position.col as usize),
+            }
+        })?;
+    element_to_value(document.root_element(), 0)
+}
+
+fn element_to_value(node: roxmltree::Node, depth: usize) -> Result<Value, RequestError> {
+    if depth > MAX_DEPTH {
+        return Err(RequestError::InvalidBody {
+            reason: "xml nested too deep".to_string(),
+            line: None,
+            column: None,
+        });
+    }
+
+    let mut attributes = Map::new();
+    for attribute in node.attributes() {
+        attributes.insert(qualified_name(attribute.namespace(), attribute.name()), Value::from(attribute.value()));
+    }
+
+    let mut text: Option<String> = None;
+    let mut children: Vec<Value> = Vec::new();
+    for child in node.children() {
+        if child.is_element() {
+            children.push(element_to_value(child, depth + 1)?);
+        } else if child.is_text() {
+            let content = child.text().unwrap_or("");
+            //the text after a child element is its tail
+            let target = match children.last_mut() {
+                Some(Value::Object(last)) => last.get_mut("tail"),
+                _ => None,
+            };
+            match target {
+                Some(Value::String(tail)) => tail.push_str(content),
+                Some(tail) => *tail = Value::from(content),
+                None => text.get_or_insert_with(String::new).push_str(content),
+            }
+        }
+    }
+
+    let tag = node.tag_name();
+    let mut element = Map::new();
+    element.insert("tag".to_string(), Value::from(qualified_name(tag.namespace(), tag.name())));
+    element.insert("attributes".to_string(), Value::Object(attributes));
+    element.insert("text".to_string(), text.map(Value::from).unwrap_or(Value::Null));
+    element.insert("tail".to_string(), Value::Null);
+    element.insert("children".to_string(), Value::Array(children));
+    Ok(Value::Object(element))
+}
+
+//names in a namespace are written `{namespace}name`, like in python's ElementTree
+fn qualified_name(namespace: Option<&str>, name: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{{{}}}{}", namespace, name),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn refused(text: &str) -> bool {
+        matches!(parse_xml(text), Err(RequestError::InvalidBody { .. }))
+    }
+
+    #[test]
+    fn parses_like_element_tree() {
+        let value = parse_xml(r#"<a xmlns:n="urn:n" id="1">text<b n:k="v">inner</b>tail<c/></a>"#).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "tag": "a",
+                "attributes": {"id": "1"},
+                "text": "text",
+                "tail": null,
+                "children": [
+                    {"tag": "b", "attributes": {"{urn:n}k": "v"}, "text": "inner", "tail": "tail", "children": []},
+                    {"tag": "c", "attributes": {}, "text": null, "tail": null, "children": []},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn refuses_document_types_and_entities() {
+        assert!(refused(r#"<!DOCTYPE a [<!ENTITY e "expanded">]><a>&e;</a>"#));
+        assert!(refused(r#"<!DOCTYPE a [<!ENTITY l0 "ha"><!ENTITY l1 "&l0;&l0;&l0;">]><a>&l1;</a>"#));
+        assert!(refused(r#"<!DOCTYPE a [<!ENTITY x SYSTEM "file:///etc/passwd">]><a>&x;</a>"#));
+        assert!(refused(r#"<!DOCTYPE a SYSTEM "http://example.com/a.dtd"><a/>"#));
+        //without a declaration there is nothing an entity could refer to
+        assert!(refused("<a>&undefined;</a>"));
+        //the predefined ones are only characters
+        assert_eq!(parse_xml("<a>&lt;&amp;</a>").unwrap()["text"], "<&");
+    }
+
+    #[test]
+    fn refuses_invalid_and_deep_documents() {
+        match parse_xml("<a>\n<b></a>") {
+            Err(RequestError::InvalidBody { line, .. }) => assert_eq!(line, Some(2)),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        let deep = format!("{}{}", "<a>".repeat(MAX_DEPTH + 2), "</a>".repeat(MAX_DEPTH + 2));
+        assert!(refused(&deep));
+        let shallow = format!("{}{}", "<a>".repeat(MAX_DEPTH), "</a>".repeat(MAX_DEPTH));
+        assert!(parse_xml(&shallow).is_ok());
+    }
+}
@@ -0,0 +1,359 @@
+extern crate brotli;
+extern crate flate2;
+extern crate zstd;
+
+use crate::config::Config;
+use crate::request::MediaType;
+use crate::response::{is_event_stream, negotiate_encoding, Body, ChunkSource, Response};
+use std::io::{self, Write};
+
+//the codings we compress with, the first ones are preferred when the client has no preference
+const CODINGS: [&str; 3] = ["br", "zstd", "gzip"];
+
+//compresses the responses for the clients accepting it
+#[derive(Debug, Clone)]
+pub struct Compression {
+    enabled: bool,
+    min_size: usize,
+    content_types: Vec<String>,
+}
+
+impl Compression {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            enabled: config.compression,
+            min_size: config.compression_min_size,
+            content_types: config.compression_types.clone(),
+        }
+    }
+
+    //compresses the body with the coding the client prefers. files are left as they are,
+    //they can have precompressed variants
+    pub fn apply(&self, accept_encoding: Option<&str>, mut response: Response) -> Response {
+        if !self.enabled
+            || !(200..300).contains(&response.code)
+            || matches!(response.code, 204 | 206)
+            || response.header("Content-Encoding").is_some()
+            //the keep-alive comments are written by the connection, between the events
+            || is_event_stream(response.header("Content-Type"))
+        {
+            return response;
+        }
+        let big_enough = match &response.body {
+            Body::Bytes(bytes) => bytes.len() >= self.min_size,
+            Body::Stream(_) => true,
+            Body::File { .. } => false,
+        };
+        let no_transform = response
+            .header("Cache-Control")
+            .map(|cache_control| cache_control.to_ascii_lowercase().contains("no-transform"))
+            .unwrap_or(false);
+        if !big_enough || no_transform || !self.allows(response.header("Content-Type")) {
+            return response;
+        }
+
+        //the response depends on the header, even for the clients that get it uncompressed
+        add_vary(&mut response, "Accept-Encoding");
+        let coding = match accept_encoding.and_then(|accept| negotiate_encoding(accept, &CODINGS)) {
+            Some(coding) => coding,
+            None => return response,
+        };
+        response.body = match std::mem::replace(&mut response.body, Body::Bytes(Vec::new())) {
+            Body::Bytes(bytes) => match compress(coding, &bytes) {
+                Ok(compressed) => Body::Bytes(compressed),
+                //sent as it is, the client accepts it too
+                Err(_) => {
+                    response.body = Body::Bytes(bytes);
+                    return response;
+                }
+            },
+            Body::Stream(source) => match Encoder::new(coding) {
+                Ok(encoder) => Body::Stream(Box::new(CompressedStream {
+                    source,
+                    encoder: Some(encoder),
+                })),
+                Err(_) => {
+                    response.body = Body::Stream(source);
+                    return response;
+                }
+            },
+            body => body,
+        };
+        response.headers.push(("Content-Encoding".to_string(), coding.to_string()));
+        weaken_etag(&mut response);
+        response
+    }
+
+    //entries like `text/` match every subtype, `+json` every suffixed type, the others the exact type
+    fn allows(&self, content_type: Option<&str>) -> bool {
+        let media_type = match content_type.and_then(MediaType::parse) {
+            Some(media_type) => media_type,
+            None => return false,
+        };
+        self.content_types.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            if allowed.ends_with('/') {
+                media_type.essence.starts_with(&allowed)
+            } else if allowed.starts_with('+') {
+                media_type.essence.ends_with(&allowed)
+            } else {
+                media_type.essence == allowed
+            }
+        })
+    }
+}
+
+fn add_vary(response: &mut Response, header: &str) {
+    for (key, value) in response.headers.iter_mut() {
+        if key.eq_ignore_ascii_case("Vary") {
+            let listed = value
+                .split(',')
+                .any(|name| name.trim() == "*" || name.trim().eq_ignore_ascii_case(header));
+            if !listed {
+                value.push_str(", ");
+                value.push_str(header);
+            }
+            return;
+        }
+    }
+    response.headers.push(("Vary".to_string(), header.to_string()));
+}
+
+//the compressed body is not byte for byte the one the etag was given to
+fn weaken_etag(response: &mut Response) {
+    for (key, value) in response.headers.iter_mut() {
+        if key.eq_ignore_ascii_case("ETag") && !value.starts_with("W/") {
+            *value = format!("W/{}", value);
+        }
+    }
+}
+
+fn compress(coding: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(coding)?;
+    encoder.write(data)?;
+    encoder.finish()
+}
+
+//a compressor writing to memory, the output can be taken while it goes
+enum Encoder {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(coding: &str) -> io::Result<Self> {
+        match coding {
+            "gzip" => Ok(Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            ))),
+            //a fast quality, the responses are compressed while the client waits
+            "br" => Ok(Encoder::Brotli(Box::new(brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22)))),
+            "zstd" => Ok(Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), 3)?)),
+            other => Err(io::Error::new(io::ErrorKind::Unsupported, format!("unknown coding {}", other))),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write_all(data),
+            Encoder::Brotli(encoder) => encoder.write_all(data),
+            Encoder::Zstd(encoder) => encoder.write_all(data),
+        }
+    }
+
+    //flushes what was written so far and takes the compressed bytes
+    fn take_output(&mut self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => {
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Encoder::Brotli(encoder) => {
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.flush()?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+//compresses every chunk of a stream as it is produced, flushing so the client gets it right away
+struct CompressedStream {
+    source: Box<dyn ChunkSource>,
+    //taken when the stream ends
+    encoder: Option<Encoder>,
+}
+
+impl ChunkSource for CompressedStream {
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let encoder = match &mut self.encoder {
+            Some(encoder) => encoder,
+            None => return Ok(None),
+        };
+        match self.source.next_chunk()? {
+            Some(chunk) => {
+                encoder.write(&chunk)?;
+                Ok(Some(encoder.take_output()?))
+            }
+            None => match self.encoder.take() {
+                Some(encoder) => Ok(Some(encoder.finish()?)),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn compression() -> Compression {
+        Compression::from_config(&Config {
+            compression_min_size: 16,
+            ..Config::default()
+        })
+    }
+
+    fn text() -> Response {
+        Response::new(200, "text/plain; charset=utf-8", "hello world ".repeat(10))
+    }
+
+    fn decompress(coding: &str, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        match coding {
+            "gzip" => flate2::read::GzDecoder::new(data).read_to_end(&mut output).unwrap(),
+            "br" => brotli::Decompressor::new(data, 4096).read_to_end(&mut output).unwrap(),
+            "zstd" => zstd::stream::read::Decoder::new(data).unwrap().read_to_end(&mut output).unwrap(),
+            _ => unreachable!(),
+        };
+        output
+    }
+
+    //a stream of the chunks given
+    struct Chunks(Vec<&'static str>);
+
+    impl ChunkSource for Chunks {
+        fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+            Ok((!self.0.is_empty()).then(|| self.0.remove(0).as_bytes().to_vec()))
+        }
+    }
+
+    #[test]
+    fn negotiates_the_preferred_coding() {
+        assert_eq!(negotiate_encoding("gzip, br", &CODINGS), Some("br"));
+        assert_eq!(negotiate_encoding("gzip;q=1, br;q=0.5", &CODINGS), Some("gzip"));
+        assert_eq!(negotiate_encoding("GZIP", &CODINGS), Some("gzip"));
+        assert_eq!(negotiate_encoding("*", &CODINGS), Some("br"));
+        assert_eq!(negotiate_encoding("*;q=0.1, zstd;q=0.5", &CODINGS), Some("zstd"));
+        assert_eq!(negotiate_encoding("identity", &CODINGS), None);
+        assert_eq!(negotiate_encoding("", &CODINGS), None);
+    }
+
+    #[test]
+    fn never_uses_a_coding_with_a_zero_quality() {
+        assert_eq!(negotiate_encoding("br;q=0, gzip", &CODINGS), Some("gzip"));
+        assert_eq!(negotiate_encoding("*;q=0", &CODINGS), None);
+        assert_eq!(negotiate_encoding("br;q=0, zstd;q=0, *;q=0", &CODINGS), None);
+        assert_eq!(negotiate_encoding("gzip;q=0.0", &["gzip"]), None);
+    }
+
+    #[test]
+    fn compresses_with_every_coding() {
+        for coding in CODINGS {
+            let response = compression().apply(Some(coding), text());
+            assert_eq!(response.header("Content-Encoding"), Some(coding));
+            assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+            let body = response.body.bytes().unwrap();
+            assert_eq!(decompress(coding, body), "hello world ".repeat(10).into_bytes());
+        }
+    }
+
+    #[test]
+    fn varies_even_when_sent_uncompressed() {
+        let response = compression().apply(Some("br;q=0"), text());
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        let response = compression().apply(None, text());
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    }
+
+    #[test]
+    fn adds_to_the_vary_header() {
+        let response = compression().apply(Some("gzip"), text().with_header("Vary", "Cookie"));
+        assert_eq!(response.header("Vary"), Some("Cookie, Accept-Encoding"));
+        let response = compression().apply(Some("gzip"), text().with_header("Vary", "accept-encoding"));
+        assert_eq!(response.header("Vary"), Some("accept-encoding"));
+        let response = compression().apply(Some("gzip"), text().with_header("Vary", "*"));
+        assert_eq!(response.header("Vary"), Some("*"));
+    }
+
+    #[test]
+    fn weakens_the_etag() {
+        let response = compression().apply(Some("gzip"), text().with_header("ETag", "\"abc\""));
+        assert_eq!(response.header("ETag"), Some("W/\"abc\""));
+        let response = compression().apply(Some("gzip"), text().with_header("ETag", "W/\"abc\""));
+        assert_eq!(response.header("ETag"), Some("W/\"abc\""));
+        //uncompressed, the body is still the one the etag was given to
+        let response = compression().apply(None, text().with_header("ETag", "\"abc\""));
+        assert_eq!(response.header("ETag"), Some("\"abc\""));
+    }
+
+    #[test]
+    fn leaves_some_responses_as_they_are() {
+        let small = Response::new(200, "text/plain", "hello");
+        let image = Response::new(200, "image/png", "hello world ".repeat(10));
+        let transform = text().with_header("Cache-Control", "public, No-Transform");
+        let encoded = text().with_header("Content-Encoding", "gzip");
+        let mut error = text();
+        error.code = 404;
+        for response in [small, image, transform, encoded, error] {
+            let before = response.header("Content-Encoding").map(str::to_string);
+            let response = compression().apply(Some("gzip"), response);
+            assert_eq!(response.header("Content-Encoding").map(str::to_string), before);
+        }
+        let disabled = Compression::from_config(&Config {
+            compression: false,
+            ..Config::default()
+        });
+        assert_eq!(disabled.apply(Some("gzip"), text()).header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn matches_the_content_types() {
+        let compression = compression();
+        assert!(compression.allows(Some("text/html")));
+        assert!(compression.allows(Some("application/json; charset=utf-8")));
+        assert!(compression.allows(Some("application/problem+json")));
+        assert!(!compression.allows(Some("image/png")));
+        assert!(!compression.allows(Some("application/jsonx")));
+        assert!(!compression.allows(None));
+    }
+
+    #[test]
+    fn compresses_a_stream_chunk_by_chunk() {
+        let response = Response::stream(200, "text/plain", Box::new(Chunks(vec!["hello ", "streamed ", "world"])));
+        let mut response = compression().apply(Some("gzip"), response);
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        let mut compressed = Vec::new();
+        if let Body::Stream(source) = &mut response.body {
+            while let Some(chunk) = source.next_chunk().unwrap() {
+                compressed.extend(chunk);
+            }
+        }
+        assert_eq!(decompress("gzip", &compressed), b"hello streamed world");
+    }
+}
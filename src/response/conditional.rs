@@ -0,0 +1,198 @@
+extern crate sha1;
+extern crate time;
+
+use crate::request::{Request, RequestMethod};
+use crate::response::{Body, Response};
+use sha1::{Digest, Sha1};
+
+//what the request asks about the response before it is sent: HEAD requests only want the
+//headers, and conditional requests only want the body when it changed
+pub struct Conditions {
+    head: bool,
+    //only GET and HEAD responses are validated
+    cacheable: bool,
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+}
+
+impl Conditions {
+    pub fn from_request(request: &Request) -> Self {
+        Self {
+            head: matches!(request.method, RequestMethod::HEAD),
+            cacheable: matches!(request.method, RequestMethod::GET | RequestMethod::HEAD),
+            if_none_match: request.headers.get("If-None-Match").cloned(),
+            if_modified_since: request.headers.get("If-Modified-Since").cloned(),
+        }
+    }
+
+    pub fn apply(&self, mut response: Response) -> Response {
+        if self.cacheable && response.code == 200 {
+            //handlers setting no validator get an etag from the body
+            if let (None, Some(body)) = (response.header("ETag"), response.body.bytes()) {
+                let etag = body_etag(body);
+                response = response.with_header("ETag", &etag);
+            }
+            if self.is_not_modified(&response) {
+                response = not_modified(response);
+            }
+        }
+        if self.head {
+            response = response.without_body();
+        }
+        response
+    }
+
+    //`If-None-Match` takes precedence, `If-Modified-Since` is only used without it
+    pub fn is_not_modified(&self, response: &Response) -> bool {
+        if let Some(if_none_match) = &self.if_none_match {
+            return match response.header("ETag") {
+                Some(etag) => etag_matches(if_none_match, etag),
+                None => false,
+            };
+        }
+        match (&self.if_modified_since, response.header("Last-Modified")) {
+            (Some(since), Some(last_modified)) => match (parse_http_date(since), parse_http_date(last_modified)) {
+                (Some(since), Some(last_modified)) => last_modified <= since,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+//a strong etag from the sha1 of the body, the same body gets the same etag from every
+//worker, process and build, so caches keep validating after a restart or behind a balancer
+pub fn body_etag(body: &[u8]) -> String {
+    let digest: String = Sha1::digest(body).iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("\"{}\"", digest)
+}
+
+//the weak comparison of `If-None-Match`, a list of etags or `*`
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim().trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+//the seconds since the epoch of a date like `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn parse_http_date(date: &str) -> Option<i64> {
+    let date = time::strptime(date.trim(), "%a, %d %b %Y %H:%M:%S GMT").ok()?;
+    Some(date.to_timespec().sec)
+}
+
+//a date like `Sun, 06 Nov 1994 08:49:37 GMT`, from the seconds since the epoch
+pub fn http_date(seconds: i64) -> String {
+    time::at_utc(time::Timespec::new(seconds, 0)).rfc822().to_string()
+}
+
+//the body is dropped, but the validators and the caching headers are kept
+fn not_modified(response: Response) -> Response {
+    let mut headers = response.headers;
+    headers.retain(|(key, _)| {
+        !["Content-Type", "Content-Encoding", "Content-Range"]
+            .iter()
+            .any(|name| key.eq_ignore_ascii_case(name))
+    });
+    Response {
+        code: 304,
+        headers,
+        body: Body::Bytes(Vec::new()),
+        omit_body: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAST_MODIFIED: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn conditions(method: RequestMethod, if_none_match: Option<&str>, if_modified_since: Option<&str>) -> Conditions {
+        Conditions {
+            head: matches!(method, RequestMethod::HEAD),
+            cacheable: matches!(method, RequestMethod::GET | RequestMethod::HEAD),
+            if_none_match: if_none_match.map(str::to_string),
+            if_modified_since: if_modified_since.map(str::to_string),
+        }
+    }
+
+    fn dated() -> Response {
+        Response::new(200, "text/plain", "hello").with_header("Last-Modified", LAST_MODIFIED)
+    }
+
+    #[test]
+    fn computes_a_stable_etag() {
+        assert_eq!(body_etag(b"hello"), "\"aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d\"");
+    }
+
+    #[test]
+    fn compares_etags_weakly() {
+        assert!(etag_matches("\"a\"", "\"a\""));
+        assert!(etag_matches("W/\"a\"", "\"a\""));
+        assert!(etag_matches("\"a\"", "W/\"a\""));
+        assert!(etag_matches("\"x\", W/\"a\"", "\"a\""));
+        assert!(etag_matches("*", "\"a\""));
+        assert!(!etag_matches("\"b\"", "\"a\""));
+        assert!(!etag_matches("\"a-suffix\"", "\"a\""));
+    }
+
+    #[test]
+    fn prefers_if_none_match_to_if_modified_since() {
+        let response = dated().with_header("ETag", "\"v1\"");
+        assert!(conditions(RequestMethod::GET, Some("\"v1\""), None).is_not_modified(&response));
+        //the date alone would match
+        assert!(!conditions(RequestMethod::GET, Some("\"v2\""), Some(LAST_MODIFIED)).is_not_modified(&response));
+        //without an etag of the response nothing matches, not even `*`
+        assert!(!conditions(RequestMethod::GET, Some("*"), Some(LAST_MODIFIED)).is_not_modified(&dated()));
+    }
+
+    #[test]
+    fn compares_the_modification_dates() {
+        let not_modified = |since: &str| conditions(RequestMethod::GET, None, Some(since)).is_not_modified(&dated());
+        assert!(not_modified(LAST_MODIFIED));
+        assert!(not_modified("Mon, 07 Nov 1994 08:49:37 GMT"));
+        assert!(!not_modified("Sun, 06 Nov 1994 08:49:36 GMT"));
+        assert!(!not_modified("yesterday"));
+        assert!(!conditions(RequestMethod::GET, None, None).is_not_modified(&dated()));
+    }
+
+    #[test]
+    fn answers_304_without_the_body() {
+        let etag = body_etag(b"hello");
+        let response = conditions(RequestMethod::GET, Some(&etag), None).apply(dated());
+        assert_eq!(response.code, 304);
+        assert_eq!(response.body.bytes(), Some(&b""[..]));
+        assert_eq!(response.header("ETag"), Some(etag.as_str()));
+        assert_eq!(response.header("Last-Modified"), Some(LAST_MODIFIED));
+        assert_eq!(response.header("Content-Type"), None);
+    }
+
+    #[test]
+    fn only_validates_successful_get_and_head_responses() {
+        let etag = body_etag(b"hello");
+        let response = conditions(RequestMethod::POST, Some(&etag), None).apply(dated());
+        assert_eq!((response.code, response.header("ETag")), (200, None));
+        let failed = Response::new(404, "text/plain", "hello");
+        assert_eq!(conditions(RequestMethod::GET, Some(&etag), None).apply(failed).code, 404);
+        //the etag of the handler is kept
+        let tagged = dated().with_header("ETag", "\"mine\"");
+        let tagged = conditions(RequestMethod::GET, None, None).apply(tagged);
+        assert_eq!(tagged.header("ETag"), Some("\"mine\""));
+    }
+
+    #[test]
+    fn drops_the_body_of_head_responses() {
+        let response = conditions(RequestMethod::HEAD, None, None).apply(dated());
+        assert_eq!(response.code, 200);
+        assert!(response.header("ETag").is_some());
+        assert!(response.omit_body);
+    }
+
+    #[test]
+    fn formats_and_parses_dates() {
+        assert_eq!(parse_http_date(LAST_MODIFIED), Some(784111777));
+        assert_eq!(http_date(784111777), LAST_MODIFIED);
+    }
+}
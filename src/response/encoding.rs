@@ -0,0 +1,36 @@
+//the quality the `Accept-Encoding` header gives to the content coding, like `gzip` or `br`.
+//codings are allowed by their name or by `*`, 0 means the coding is not accepted
+pub fn encoding_quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = 0.0;
+    for entry in accept_encoding.split(',') {
+        let mut params = entry.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return quality;
+        }
+        if name == "*" {
+            wildcard = quality;
+        }
+    }
+    wildcard
+}
+
+pub fn accepts_encoding(accept_encoding: &str, coding: &str) -> bool {
+    encoding_quality(accept_encoding, coding) > 0.0
+}
+
+//the supported coding with the best quality, ties are broken by the order of `supported`
+pub fn negotiate_encoding<'a>(accept_encoding: &str, supported: &[&'a str]) -> Option<&'a str> {
+    let mut best: Option<(&'a str, f32)> = None;
+    for coding in supported {
+        let quality = encoding_quality(accept_encoding, coding);
+        if quality > 0.0 && best.map(|(_, best)| quality > best).unwrap_or(true) {
+            best = Some((coding, quality));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
@@ -0,0 +1,165 @@
+extern crate pyo3;
+
+use crate::request::Request;
+use crate::response::Response;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyString};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static ERROR_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize, Debug)]
+pub struct ErrorFrame {
+    pub filename: String,
+    pub lineno: usize,
+    pub name: String,
+    pub line: String,
+    //the repr of every local variable of the frame
+    pub locals: BTreeMap<String, String>,
+}
+
+//everything we know about an exception raised while handling a request
+#[derive(Serialize, Debug)]
+pub struct ErrorReport {
+    //identifies the error in the logs, it is the only detail sent to the client in production
+    pub id: String,
+    pub exception: String,
+    pub traceback: String,
+    pub frames: Vec<ErrorFrame>,
+}
+
+impl ErrorReport {
+    pub fn from_error(py: Python, err: &PyErr) -> Self {
+        let (traceback, frames) = match Self::collect_frames(py, err) {
+            Ok(collected) => collected,
+            //the traceback module failed, we still know the exception itself
+            Err(_) => (err.to_string(), Vec::new()),
+        };
+        Self {
+            id: new_error_id(),
+            exception: err.to_string(),
+            traceback,
+            frames,
+        }
+    }
+
+    fn collect_frames(py: Python, err: &PyErr) -> PyResult<(String, Vec<ErrorFrame>)> {
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("capture_locals", true)?;
+        let exception = py.import("traceback")?.getattr("TracebackException")?.call(
+            (err.get_type(py), err.value(py), err.traceback(py)),
+            Some(kwargs),
+        )?;
+        let traceback: String = PyString::new(py, "")
+            .call_method1("join", (exception.call_method0("format")?,))?
+            .extract()?;
+
+        let mut frames = Vec::new();
+        for frame in exception.getattr("stack")?.iter()? {
+            let frame = frame?;
+            let locals: Option<BTreeMap<String, String>> = frame.getattr("locals")?.extract()?;
+            frames.push(ErrorFrame {
+                filename: frame.getattr("filename")?.extract()?,
+                lineno: frame.getattr("lineno")?.extract::<Option<usize>>()?.unwrap_or(0),
+                name: frame.getattr("name")?.extract()?,
+                line: frame.getattr("line")?.extract::<Option<String>>()?.unwrap_or_default(),
+                locals: locals.unwrap_or_default(),
+            });
+        }
+        Ok((traceback, frames))
+    }
+
+    //the page shown in debug mode, with the traceback, the locals and the request
+    pub fn debug_response(&self, request: Option<&Request>) -> Response {
+        let wants_json = request
+            .and_then(|request| request.headers.get("Accept"))
+            .map(|accept| accept.contains("json"))
+            .unwrap_or(false);
+
+        let response = if wants_json {
+            let body = serde_json::json!({
+                "error": self,
+                "request": request,
+            });
+            Response::new(500, "application/json", body.to_string())
+        } else {
+            Response::new(500, "text/html; charset=utf-8", self.render_html(request))
+        };
+        response.with_header("X-Error-Id", &self.id)
+    }
+
+    //the page shown in production, only the id of the error is given to the client
+    pub fn generic_response(&self) -> Response {
+        let body = format!("Internal Server Error\nError id: {}\n", self.id);
+        Response::new(500, "text/plain; charset=utf-8", body).with_header("X-Error-Id", &self.id)
+    }
+
+    fn render_html(&self, request: Option<&Request>) -> String {
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html><html><head><meta charset=\"utf-8\">");
+        html.push_str(&format!("<title>{}</title>", escape_html(&self.exception)));
+        html.push_str(
+            "<style>body{font-family:sans-serif;margin:2em}pre{background:#f4f4f4;padding:.5em}\
+             .frame{border-top:1px solid #ddd;padding:.5em 0}td{font-family:monospace;padding:0 1em 0 0;vertical-align:top}</style>",
+        );
+        html.push_str("</head><body>");
+        html.push_str(&format!("<h1>{}</h1>", escape_html(&self.exception)));
+        html.push_str(&format!("<p>Error id: {}</p>", escape_html(&self.id)));
+
+        html.push_str("<h2>Traceback</h2>");
+        for frame in &self.frames {
+            html.push_str("<div class=\"frame\">");
+            html.push_str(&format!(
+                "<h3>{}, line {}, in {}</h3><pre>{}</pre>",
+                escape_html(&frame.filename),
+                frame.lineno,
+                escape_html(&frame.name),
+                escape_html(&frame.line),
+            ));
+            html.push_str("<table>");
+            for (name, value) in &frame.locals {
+                html.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td></tr>",
+                    escape_html(name),
+                    escape_html(value)
+                ));
+            }
+            html.push_str("</table></div>");
+        }
+        html.push_str(&format!("<pre>{}</pre>", escape_html(&self.traceback)));
+
+        if let Some(request) = request {
+            let request = serde_json::to_string_pretty(request).unwrap_or_default();
+            html.push_str(&format!("<h2>Request</h2><pre>{}</pre>", escape_html(&request)));
+        }
+        html.push_str("</body></html>");
+        html
+    }
+}
+
+fn new_error_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+    let count = ERROR_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:04x}", now, count & 0xffff)
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
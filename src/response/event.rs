@@ -0,0 +1,149 @@
+use std::fmt::Write;
+
+//sent on idle event streams, so the proxies and the clients do not drop them
+pub const KEEP_ALIVE_COMMENT: &[u8] = b": keep-alive\n\n";
+
+//whether the content type is the one of server-sent events
+pub fn is_event_stream(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|media_type| media_type.trim().eq_ignore_ascii_case("text/event-stream"))
+        .unwrap_or(false)
+}
+
+//a server-sent event, every field is optional
+#[derive(Debug, Default, Clone)]
+pub struct Event {
+    pub event: Option<String>,
+    pub data: Option<String>,
+    pub id: Option<String>,
+    //how long the client waits before reconnecting, in milliseconds
+    pub retry: Option<u64>,
+    pub comment: Option<String>,
+}
+
+impl Event {
+    pub fn validate(&self) -> Result<(), String> {
+        let single_line = |value: &Option<String>| value.as_ref().map(|value| !value.contains(['\r', '\n'])).unwrap_or(true);
+        if !single_line(&self.event) {
+            return Err("the event name can not have line breaks".to_string());
+        }
+        //the client would ignore the id
+        if !single_line(&self.id) || self.id.as_ref().map(|id| id.contains('\0')).unwrap_or(false) {
+            return Err("the event id can not have line breaks or null characters".to_string());
+        }
+        Ok(())
+    }
+
+    //the lines of the event, the data can span several `data:` lines
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut frame = String::new();
+        if let Some(comment) = &self.comment {
+            for line in lines(comment) {
+                let _ = writeln!(frame, ": {}", line);
+            }
+        }
+        if let Some(event) = &self.event {
+            let _ = writeln!(frame, "event: {}", event);
+        }
+        if let Some(id) = &self.id {
+            let _ = writeln!(frame, "id: {}", id);
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(frame, "retry: {}", retry);
+        }
+        if let Some(data) = &self.data {
+            for line in lines(data) {
+                let _ = writeln!(frame, "data: {}", line);
+            }
+        }
+        frame.push('\n');
+        frame.into_bytes()
+    }
+}
+
+//splits on any of the line breaks the clients understand
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(text);
+    std::iter::from_fn(move || {
+        let text = rest?;
+        match text.find(['\r', '\n']) {
+            Some(idx) => {
+                let skip = if text[idx..].starts_with("\r\n") { 2 } else { 1 };
+                rest = Some(&text[idx + skip..]);
+                Some(&text[..idx])
+            }
+            None => {
+                rest = None;
+                Some(text)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(data: &str) -> String {
+        let event = Event {
+            data: Some(data.to_string()),
+            ..Event::default()
+        };
+        String::from_utf8(event.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn writes_every_field() {
+        let event = Event {
+            event: Some("tick".to_string()),
+            data: Some("{\"n\": 1}".to_string()),
+            id: Some("1".to_string()),
+            retry: Some(3000),
+            comment: Some("hello".to_string()),
+        };
+        let frame = String::from_utf8(event.to_bytes()).unwrap();
+        assert_eq!(frame, ": hello\nevent: tick\nid: 1\nretry: 3000\ndata: {\"n\": 1}\n\n");
+    }
+
+    #[test]
+    fn splits_the_data_in_lines() {
+        assert_eq!(data("one"), "data: one\n\n");
+        assert_eq!(data("one\ntwo"), "data: one\ndata: two\n\n");
+        assert_eq!(data("one\r\ntwo\rthree"), "data: one\ndata: two\ndata: three\n\n");
+        //the empty lines are kept, the client joins the lines back with line feeds
+        assert_eq!(data("one\n\ntwo"), "data: one\ndata: \ndata: two\n\n");
+        assert_eq!(data("one\n"), "data: one\ndata: \n\n");
+        assert_eq!(data(""), "data: \n\n");
+    }
+
+    #[test]
+    fn splits_the_comments_in_lines() {
+        let event = Event {
+            comment: Some("one\ntwo".to_string()),
+            ..Event::default()
+        };
+        assert_eq!(event.to_bytes(), b": one\n: two\n\n");
+    }
+
+    #[test]
+    fn refuses_line_breaks_in_the_name_and_id() {
+        let event = |event: &str, id: &str| Event {
+            event: Some(event.to_string()),
+            id: Some(id.to_string()),
+            ..Event::default()
+        };
+        assert!(event("tick", "1").validate().is_ok());
+        assert!(event("tick\ndata: injected", "1").validate().is_err());
+        assert!(event("tick", "1\r").validate().is_err());
+        assert!(event("tick", "1\0").validate().is_err());
+    }
+
+    #[test]
+    fn recognizes_event_streams() {
+        assert!(is_event_stream(Some("text/event-stream")));
+        assert!(is_event_stream(Some("Text/Event-Stream; charset=utf-8")));
+        assert!(!is_event_stream(Some("text/plain")));
+        assert!(!is_event_stream(None));
+    }
+}
@@ -0,0 +1,14 @@
+pub mod compression;
+pub mod conditional;
+pub mod encoding;
+pub mod error_page;
+pub mod event;
+#[allow(clippy::module_inception)]
+pub mod response;
+
+pub use compression::*;
+pub use conditional::*;
+pub use encoding::*;
+pub use error_page::*;
+pub use event::*;
+pub use response::*;
@@ -0,0 +1,274 @@
+extern crate time;
+
+use crate::response::is_event_stream;
+use std::fs::File;
+use std::io;
+
+//produces the body of a streamed response, piece by piece
+pub trait ChunkSource {
+    //the next piece, None once the body is over
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+//what is sent after the head of a response
+pub enum Body {
+    Bytes(Vec<u8>),
+    //a part of a file, read while it is written to the client
+    File { file: File, offset: u64, length: u64 },
+    //a body of unknown length, sent with the chunked transfer encoding
+    Stream(Box<dyn ChunkSource>),
+}
+
+impl Body {
+    //the length of the body, unknown for streams
+    pub fn length(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File { length, .. } => Some(*length),
+            Body::Stream(_) => None,
+        }
+    }
+
+    //the content, when it is in memory
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::File { .. } | Body::Stream(_) => None,
+        }
+    }
+}
+
+//an http response, ready to be written to the client
+pub struct Response {
+    pub code: i32,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+    //HEAD responses announce the length of the body without sending it
+    pub omit_body: bool,
+}
+
+impl Response {
+    pub fn new(code: i32, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            code,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: Body::Bytes(body.into()),
+            omit_body: false,
+        }
+    }
+
+    //sends `length` bytes of the file, starting at `offset`
+    pub fn file(code: i32, content_type: &str, file: File, offset: u64, length: u64) -> Self {
+        Self {
+            code,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: Body::File { file, offset, length },
+            omit_body: false,
+        }
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn stream(code: i32, content_type: &str, source: Box<dyn ChunkSource>) -> Self {
+        Self {
+            code,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: Body::Stream(source),
+            omit_body: false,
+        }
+    }
+
+    pub fn without_body(mut self) -> Self {
+        self.omit_body = true;
+        self
+    }
+
+    //returns the first value of the header, ignoring the case of the name
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    //splits the response in what the connection sends first and the body, which is None when
+    //nothing has to be sent after the head
+    pub fn into_parts(self) -> (ResponseHead, Option<Body>) {
+        //these responses never have a body
+        let length = match (self.code, self.body.length()) {
+            (100..=199 | 204 | 304, _) => BodyLength::None,
+            (_, Some(length)) => BodyLength::Known(length),
+            (_, None) => BodyLength::Streamed,
+        };
+        let body = match length {
+            BodyLength::None => None,
+            _ if self.omit_body => None,
+            _ => Some(self.body),
+        };
+        let head = ResponseHead {
+            code: self.code,
+            headers: self.headers,
+            length,
+        };
+        (head, body)
+    }
+}
+
+//how the length of the body is told to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyLength {
+    //the status never has a body
+    None,
+    Known(u64),
+    //sent as it is produced, with the chunked transfer encoding on http/1.1
+    Streamed,
+}
+
+//the status and the headers of a response
+#[derive(Debug, Clone)]
+pub struct ResponseHead {
+    pub code: i32,
+    pub headers: Vec<(String, String)>,
+    pub length: BodyLength,
+}
+
+impl ResponseHead {
+    //returns the first value of the header, ignoring the case of the name
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_event_stream(&self) -> bool {
+        is_event_stream(self.header("Content-Type"))
+    }
+
+    //whether the handler asked to close the connection after this response
+    pub fn closes_connection(&self) -> bool {
+        self.header("Connection")
+            .map(|connection| connection.split(',').any(|token| token.trim().eq_ignore_ascii_case("close")))
+            .unwrap_or(false)
+    }
+
+    //streamed bodies are sent with the chunked transfer encoding, or without any framing when
+    //`chunked` is false, the end of the body is then the end of the connection
+    pub fn to_http1(&self, keep_alive: bool, chunked: bool) -> Vec<u8> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nDate: {}\r\nServer: Someserver\r\n",
+            self.code,
+            reason_phrase(self.code),
+            time::now_utc().rfc822(),
+        );
+        match self.length {
+            BodyLength::None => (),
+            BodyLength::Known(length) => head.push_str(&format!("Content-Length: {}\r\n", length)),
+            BodyLength::Streamed if chunked => head.push_str("Transfer-Encoding: chunked\r\n"),
+            BodyLength::Streamed => (),
+        }
+        for (key, value) in &self.headers {
+            //the connection decides, not the handler, except for the upgrades
+            if key.eq_ignore_ascii_case("Connection") && self.code != 101 {
+                continue;
+            }
+            head.push_str(key);
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        if !keep_alive {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+}
+
+pub fn reason_phrase(code: i32) -> &'static str {
+    match code {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        422 => "Unprocessable Entity",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(length: BodyLength) -> ResponseHead {
+        ResponseHead {
+            code: 200,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string()), ("Connection".to_string(), "keep-alive".to_string())],
+            length,
+        }
+    }
+
+    fn to_http1(head: &ResponseHead, keep_alive: bool, chunked: bool) -> String {
+        String::from_utf8(head.to_http1(keep_alive, chunked)).unwrap()
+    }
+
+    #[test]
+    fn writes_the_length() {
+        let written = to_http1(&head(BodyLength::Known(5)), true, true);
+        assert!(written.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(written.contains("Content-Length: 5\r\n"));
+        assert!(written.contains("Content-Type: text/plain\r\n"));
+        assert!(written.ends_with("\r\n\r\n"));
+        assert!(!written.contains("Transfer-Encoding"));
+        //the connection header of the handler is not sent
+        assert!(!written.contains("Connection"));
+        assert!(!to_http1(&head(BodyLength::None), true, true).contains("Content-Length"));
+    }
+
+    #[test]
+    fn streams_with_the_chunked_encoding() {
+        let written = to_http1(&head(BodyLength::Streamed), true, true);
+        assert!(written.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!written.contains("Content-Length"));
+    }
+
+    #[test]
+    fn streams_without_framing_to_the_http1_0_clients() {
+        let written = to_http1(&head(BodyLength::Streamed), false, false);
+        assert!(!written.contains("Transfer-Encoding"));
+        assert!(!written.contains("Content-Length"));
+        assert!(written.contains("Connection: close\r\n"));
+    }
+}
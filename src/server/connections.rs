@@ -0,0 +1,148 @@
+extern crate tokio;
+
+use crate::config::Config;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+//how many connections are served at once, in total and for every address
+pub struct ConnectionLimits {
+    permits: Arc<Semaphore>,
+    max_per_ip: Option<usize>,
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+//an open connection, its room is given back when it is dropped
+pub struct ConnectionSlot {
+    _permit: OwnedSemaphorePermit,
+    ip: Option<IpAddr>,
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(config.max_connections)),
+            max_per_ip: config.max_connections_per_ip,
+            open: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    //waits until a connection can be served, the next ones wait in the backlog meanwhile
+    pub async fn room(&self) -> OwnedSemaphorePermit {
+        self.permits.clone().acquire_owned().await.unwrap()
+    }
+
+    //None when the address already has all the connections it can have
+    pub fn admit(&self, permit: OwnedSemaphorePermit, ip: IpAddr) -> Option<ConnectionSlot> {
+        let max_per_ip = match self.max_per_ip {
+            Some(max_per_ip) => max_per_ip,
+            None => {
+                return Some(ConnectionSlot {
+                    _permit: permit,
+                    ip: None,
+                    open: self.open.clone(),
+                })
+            }
+        };
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(ip).or_insert(0);
+        if *count >= max_per_ip {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionSlot {
+            _permit: permit,
+            ip: Some(ip),
+            open: self.open.clone(),
+        })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let ip = match self.ip {
+            Some(ip) => ip,
+            None => return,
+        };
+        let mut open = self.open.lock().unwrap();
+        if let Some(count) = open.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limits(max_connections: usize, max_connections_per_ip: Option<usize>) -> ConnectionLimits {
+        ConnectionLimits::from_config(&Config {
+            max_connections,
+            max_connections_per_ip,
+            ..Config::default()
+        })
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn caps_the_connections_of_an_address() {
+        let limits = limits(10, Some(2));
+        let first = limits.admit(limits.room().await, ip("10.0.0.1")).unwrap();
+        let _second = limits.admit(limits.room().await, ip("10.0.0.1")).unwrap();
+        assert!(limits.admit(limits.room().await, ip("10.0.0.1")).is_none());
+        //the other addresses have their own count
+        assert!(limits.admit(limits.room().await, ip("10.0.0.2")).is_some());
+        assert!(limits.admit(limits.room().await, ip("::1")).is_some());
+        //closing a connection makes room for another one
+        drop(first);
+        assert!(limits.admit(limits.room().await, ip("10.0.0.1")).is_some());
+    }
+
+    #[tokio::test]
+    async fn forgets_the_addresses_without_connections() {
+        let limits = limits(10, Some(2));
+        let slot = limits.admit(limits.room().await, ip("10.0.0.1")).unwrap();
+        assert_eq!(limits.open.lock().unwrap().get(&ip("10.0.0.1")), Some(&1));
+        drop(slot);
+        assert!(limits.open.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refusing_an_address_gives_its_room_back() {
+        let limits = limits(2, Some(1));
+        let _slot = limits.admit(limits.room().await, ip("10.0.0.1")).unwrap();
+        assert!(limits.admit(limits.room().await, ip("10.0.0.1")).is_none());
+        assert_eq!(limits.permits.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn caps_the_connections_in_total() {
+        let limits = limits(2, None);
+        let first = limits.admit(limits.room().await, ip("10.0.0.1")).unwrap();
+        let _second = limits.admit(limits.room().await, ip("10.0.0.2")).unwrap();
+        //the next connection waits until one is closed
+        assert!(tokio::time::timeout(Duration::from_millis(50), limits.room()).await.is_err());
+        drop(first);
+        assert!(tokio::time::timeout(Duration::from_millis(50), limits.room()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn admits_every_connection_of_an_address_without_a_cap() {
+        let limits = limits(10, None);
+        let mut slots = Vec::new();
+        for _ in 0..10 {
+            slots.push(limits.admit(limits.room().await, ip("10.0.0.1")).unwrap());
+        }
+        assert!(limits.open.lock().unwrap().is_empty());
+        assert_eq!(limits.permits.available_permits(), 0);
+    }
+}
@@ -0,0 +1,130 @@
+extern crate tokio;
+
+use crate::request::read_request;
+use crate::response::{BodyLength, ResponseHead};
+use crate::server::{next_chunk, simple_response, unavailable, websocket, ConnectionInfo, ReplyEvent, Server, WebSocketBridge};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+
+//what the connection does after a response
+enum Next {
+    KeepAlive,
+    Close,
+    //the client and the handler speak websocket from now on
+    Upgrade(WebSocketBridge),
+}
+
+//answers the requests of the connection one after the other, until one of them closes it.
+//`buffer` has the bytes already read from the connection
+pub async fn serve<S>(server: &Server, stream: S, mut buffer: Vec<u8>, connection: ConnectionInfo)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufWriter::new(stream);
+    //the first request has to come as soon as the connection is open
+    let mut wait = server.read_timeouts.header;
+    loop {
+        //the client can close the connection between two requests, or keep it idle for too long
+        if buffer.is_empty() {
+            match tokio::time::timeout(wait, stream.read_buf(&mut buffer)).await {
+                Ok(Ok(0)) | Ok(Err(_)) | Err(_) => break,
+                Ok(Ok(_)) => (),
+            }
+        }
+        wait = server.idle_timeout;
+        //the malformed requests are answered here without bothering the workers
+        let mut request = match read_request(&mut stream, &mut buffer, &server.limits, &server.read_timeouts).await {
+            Ok(request) => request,
+            Err(err) => {
+                let (head, body) = simple_response(err.response());
+                let _ = write_simple(&mut stream, &head, &body).await;
+                break;
+            }
+        };
+        connection.apply(&mut request);
+        let (keep_alive, chunked) = (request.keep_alive, !request.http1_0);
+        let mut receiver = server.dispatch(request);
+        match write_reply(server, &mut stream, &mut receiver, keep_alive, chunked).await {
+            Ok(Next::KeepAlive) => (),
+            Ok(Next::Upgrade(bridge)) => {
+                return websocket::serve(stream.into_inner(), buffer, bridge, server.websocket_limits).await;
+            }
+            Ok(Next::Close) | Err(_) => break,
+        }
+    }
+    let _ = stream.shutdown().await;
+}
+
+//writes the response of the worker, returns whether the connection can take another request.
+//the clients not understanding the chunked encoding get the streamed bodies as they are, and
+//the connection is closed to end them
+async fn write_reply<S>(
+    server: &Server,
+    stream: &mut S,
+    receiver: &mut mpsc::Receiver<ReplyEvent>,
+    keep_alive: bool,
+    chunked: bool,
+) -> io::Result<Next>
+where
+    S: AsyncWrite + Unpin,
+{
+    let (head, has_body) = match receiver.recv().await {
+        Some(ReplyEvent::Head { head, body }) => (head, body),
+        Some(ReplyEvent::Upgrade { head, bridge }) => {
+            stream.write_all(&head.to_http1(true, chunked)).await?;
+            stream.flush().await?;
+            return Ok(Next::Upgrade(bridge));
+        }
+        _ => {
+            let (head, body) = unavailable();
+            write_simple(stream, &head, &body).await?;
+            return Ok(Next::Close);
+        }
+    };
+    let streamed = has_body && head.length == BodyLength::Streamed;
+    let keep_alive = keep_alive && !head.closes_connection() && (chunked || !streamed);
+    stream.write_all(&head.to_http1(keep_alive, chunked)).await?;
+    let chunked = streamed && chunked;
+    let event_stream_keep_alive = (streamed && head.is_event_stream()).then_some(server.sse_keep_alive);
+    loop {
+        match next_chunk(receiver, event_stream_keep_alive).await {
+            Some(ReplyEvent::Chunk(chunk)) if streamed => {
+                if chunked {
+                    stream.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await?;
+                    stream.write_all(&chunk).await?;
+                    stream.write_all(b"\r\n").await?;
+                } else {
+                    stream.write_all(&chunk).await?;
+                }
+                //the client gets every chunk as soon as it is produced
+                stream.flush().await?;
+            }
+            Some(ReplyEvent::Chunk(chunk)) => stream.write_all(&chunk).await?,
+            Some(ReplyEvent::End) => break,
+            //the body failed halfway, the client only sees the connection close
+            Some(ReplyEvent::Head { .. }) | Some(ReplyEvent::Upgrade { .. }) | None => {
+                stream.flush().await?;
+                return Ok(Next::Close);
+            }
+        }
+    }
+    if chunked {
+        stream.write_all(b"0\r\n\r\n").await?;
+    }
+    stream.flush().await?;
+    match keep_alive {
+        true => Ok(Next::KeepAlive),
+        false => Ok(Next::Close),
+    }
+}
+
+//writes a response made by the connection, the connection is closed after it
+async fn write_simple<S>(stream: &mut S, head: &ResponseHead, body: &[u8]) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream.write_all(&head.to_http1(false, true)).await?;
+    stream.write_all(body).await?;
+    stream.flush().await
+}
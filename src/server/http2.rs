@@ -0,0 +1,372 @@
+extern crate bytes;
+extern crate h2;
+extern crate http;
+extern crate tempfile;
+extern crate tokio;
+
+use crate::request::{BodyLimits, MediaType, RawRequest, ReadTimeouts, RequestError, MAX_HEADERS, MAX_HEAD_SIZE, MAX_URI_LENGTH};
+use crate::response::{BodyLength, ResponseHead};
+use crate::server::{next_chunk, simple_response, unavailable, ConnectionInfo, ReplyEvent, Server};
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::{Reason, RecvStream, SendStream};
+use http::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, HOST};
+use http::request::Parts;
+use http::StatusCode;
+use std::fs::File;
+use std::future::poll_fn;
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+
+//the streams a client can have open at once on a connection
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+//how much a client can send before waiting for us to read it, for each stream and for the connection
+const WINDOW_SIZE: u32 = 1024 * 1024;
+//the headers only meaningful to http/1 connections, never sent on http/2
+const CONNECTION_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+//answers the streams of the connection, each one goes to the worker pool on its own
+pub async fn serve<S>(server: Arc<Server>, stream: S, connection: ConnectionInfo)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let handshake = h2::server::Builder::new()
+        .max_concurrent_streams(MAX_CONCURRENT_STREAMS)
+        .initial_window_size(WINDOW_SIZE)
+        .initial_connection_window_size(WINDOW_SIZE)
+        //hpack makes the headers small on the wire, their decoded size has the http/1 limit
+        .max_header_list_size(MAX_HEAD_SIZE as u32)
+        .handshake::<_, Bytes>(stream);
+    let mut h2_connection = match tokio::time::timeout(server.read_timeouts.header, handshake).await {
+        Ok(Ok(h2_connection)) => h2_connection,
+        Ok(Err(_)) | Err(_) => return,
+    };
+    let open_streams = Arc::new(AtomicUsize::new(0));
+    let mut closing = false;
+    //accepting also drives the connection, the streams only make progress while it runs
+    loop {
+        //the connection is closed once it has no streams for a whole idle timeout, and dropped
+        //if the client did not let it close by the next one
+        let accepted = tokio::select! {
+            accepted = h2_connection.accept() => accepted,
+            _ = tokio::time::sleep(server.idle_timeout) => {
+                if open_streams.load(Ordering::SeqCst) > 0 {
+                    continue;
+                }
+                if closing {
+                    break;
+                }
+                h2_connection.graceful_shutdown();
+                closing = true;
+                continue;
+            }
+        };
+        let (request, respond) = match accepted {
+            Some(Ok(accepted)) => accepted,
+            Some(Err(_)) | None => break,
+        };
+        let server = server.clone();
+        let connection = connection.clone();
+        let open = OpenStream::new(&open_streams);
+        tokio::spawn(async move {
+            serve_stream(&server, request, respond, &connection).await;
+            drop(open);
+        });
+    }
+}
+
+//counts the streams of a connection that are still being answered
+struct OpenStream(Arc<AtomicUsize>);
+
+impl OpenStream {
+    fn new(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count.clone())
+    }
+}
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn serve_stream(
+    server: &Server,
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    connection: &ConnectionInfo,
+) {
+    //the body is kept until the response is sent, dropping it would reset the stream
+    let (parts, mut body) = request.into_parts();
+    let mut request = match read_request(&parts, &mut body, &server.limits, &server.read_timeouts).await {
+        Ok(request) => request,
+        Err(err) => {
+            let (head, body) = simple_response(err.response());
+            let _ = send_simple(&mut respond, &head, body).await;
+            return;
+        }
+    };
+    connection.apply(&mut request);
+    let mut receiver = server.dispatch(request);
+    let _ = send_reply(server, &mut respond, &mut receiver).await;
+}
+
+//receives the whole body and rebuilds the request as the http/1 request the parser reads
+async fn read_request(
+    parts: &Parts,
+    body: &mut RecvStream,
+    limits: &BodyLimits,
+    timeouts: &ReadTimeouts,
+) -> Result<RawRequest, RequestError> {
+    //the head is checked before waiting for the body
+    let mut raw = http1_head(parts)?;
+    let is_multipart = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(MediaType::parse)
+        .map(|media_type| media_type.is_multipart())
+        .unwrap_or(false);
+    let max_size = if is_multipart { limits.max_upload_size } else { limits.max_body_size };
+    //the announced length is only trusted to refuse the body early
+    let content_length = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.trim().parse::<usize>().ok());
+    if content_length.map(|length| length > max_size).unwrap_or(false) {
+        return Err(RequestError::PayloadTooLarge);
+    }
+
+    let storage_error = |_| RequestError::BadRequest("could not store the body".to_string());
+    let mut bytes = Vec::new();
+    let mut spooled_body: Option<File> = None;
+    let mut length = 0;
+    while let Some(data) = receive_data(body, timeouts.body).await? {
+        let data = data.map_err(|_| RequestError::Incomplete)?;
+        let _ = body.flow_control().release_capacity(data.len());
+        length += data.len();
+        if length > max_size {
+            return Err(RequestError::PayloadTooLarge);
+        }
+        //big multipart bodies are written to a temporary file, like the http/1 ones
+        if is_multipart && spooled_body.is_none() && length > limits.upload_memory_size {
+            let mut file = tempfile::tempfile().map_err(storage_error)?;
+            file.write_all(&bytes).map_err(storage_error)?;
+            bytes.clear();
+            spooled_body = Some(file);
+        }
+        match &mut spooled_body {
+            Some(file) => file.write_all(&data).map_err(storage_error)?,
+            None => bytes.extend_from_slice(&data),
+        }
+    }
+    if let Some(file) = &mut spooled_body {
+        file.seek(SeekFrom::Start(0)).map_err(storage_error)?;
+    }
+
+    raw.extend_from_slice(format!("Content-Length: {}\r\n\r\n", length).as_bytes());
+    raw.extend_from_slice(&bytes);
+    Ok(RawRequest {
+        bytes: raw,
+        spooled_body,
+        http2: true,
+        ..Default::default()
+    })
+}
+
+//the next frame of the body, None once the body is complete
+async fn receive_data(body: &mut RecvStream, timeout: Duration) -> Result<Option<Result<Bytes, h2::Error>>, RequestError> {
+    tokio::time::timeout(timeout, body.data()).await.map_err(|_| RequestError::Timeout)
+}
+
+//the request line and the headers, under the limits of the http/1 requests. the length of the
+//body actually received is added once it is read
+fn http1_head(parts: &Parts) -> Result<Vec<u8>, RequestError> {
+    let target = parts.uri.path_and_query().map(|target| target.as_str()).unwrap_or("/");
+    if target.len() > MAX_URI_LENGTH {
+        return Err(RequestError::UriTooLong);
+    }
+    let mut head = format!("{} {} HTTP/1.1\r\n", parts.method, target).into_bytes();
+    //the content length is always added
+    let mut headers = 1;
+    if !parts.headers.contains_key(HOST) {
+        if let Some(authority) = parts.uri.authority() {
+            head.extend_from_slice(format!("Host: {}\r\n", authority).as_bytes());
+            headers += 1;
+        }
+    }
+    for (name, value) in &parts.headers {
+        if name == CONTENT_LENGTH {
+            continue;
+        }
+        //a line break would smuggle another header
+        if value.as_bytes().iter().any(|byte| matches!(byte, b'\r' | b'\n')) {
+            return Err(RequestError::BadRequest(format!("invalid {} header", name)));
+        }
+        head.extend_from_slice(name.as_str().as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+        headers += 1;
+    }
+    if headers > MAX_HEADERS || head.len() > MAX_HEAD_SIZE {
+        return Err(RequestError::HeadersTooLarge);
+    }
+    Ok(head)
+}
+
+//sends the response of the worker, the stream is reset when the body fails halfway
+async fn send_reply(
+    server: &Server,
+    respond: &mut SendResponse<Bytes>,
+    receiver: &mut mpsc::Receiver<ReplyEvent>,
+) -> Result<(), h2::Error> {
+    let (head, has_body) = match receiver.recv().await {
+        Some(ReplyEvent::Head { head, body }) => (head, body),
+        _ => {
+            let (head, body) = unavailable();
+            return send_simple(respond, &head, body).await;
+        }
+    };
+    let mut stream = respond.send_response(response_head(&head), !has_body)?;
+    if !has_body {
+        return Ok(());
+    }
+    let streamed = head.length == BodyLength::Streamed;
+    let event_stream_keep_alive = (streamed && head.is_event_stream()).then_some(server.sse_keep_alive);
+    loop {
+        match next_chunk(receiver, event_stream_keep_alive).await {
+            Some(ReplyEvent::Chunk(chunk)) => send_data(&mut stream, Bytes::from(chunk)).await?,
+            Some(ReplyEvent::End) => return stream.send_data(Bytes::new(), true),
+            Some(ReplyEvent::Head { .. }) | Some(ReplyEvent::Upgrade { .. }) | None => {
+                stream.send_reset(Reason::INTERNAL_ERROR);
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn send_simple(respond: &mut SendResponse<Bytes>, head: &ResponseHead, body: Vec<u8>) -> Result<(), h2::Error> {
+    let mut stream = respond.send_response(response_head(head), body.is_empty())?;
+    if body.is_empty() {
+        return Ok(());
+    }
+    send_data(&mut stream, Bytes::from(body)).await?;
+    stream.send_data(Bytes::new(), true)
+}
+
+//sends the data as the client makes room for it, so a slow client holds back the worker
+async fn send_data(stream: &mut SendStream<Bytes>, mut data: Bytes) -> Result<(), h2::Error> {
+    while !data.is_empty() {
+        stream.reserve_capacity(data.len());
+        let capacity = match poll_fn(|cx| stream.poll_capacity(cx)).await {
+            Some(capacity) => capacity?,
+            //the client reset the stream
+            None => return Err(Reason::CANCEL.into()),
+        };
+        if capacity == 0 {
+            continue;
+        }
+        stream.send_data(data.split_to(capacity.min(data.len())), false)?;
+    }
+    Ok(())
+}
+
+fn response_head(head: &ResponseHead) -> http::Response<()> {
+    let mut response = http::Response::new(());
+    *response.status_mut() = u16::try_from(head.code)
+        .ok()
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let headers = response.headers_mut();
+    if let Ok(date) = HeaderValue::from_str(&time::now_utc().rfc822().to_string()) {
+        headers.insert("date", date);
+    }
+    headers.insert("server", HeaderValue::from_static("Someserver"));
+    if let BodyLength::Known(length) = head.length {
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+    }
+    for (key, value) in &head.headers {
+        let name = match HeaderName::from_bytes(key.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if CONNECTION_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if let Ok(value) = HeaderValue::from_str(value) {
+            headers.append(name, value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(request: http::request::Builder) -> Parts {
+        request.body(()).unwrap().into_parts().0
+    }
+
+    fn code(result: Result<Vec<u8>, RequestError>) -> i32 {
+        result.err().map(|err| err.code()).unwrap_or(200)
+    }
+
+    #[test]
+    fn rebuilds_the_head() {
+        let parts = parts(
+            http::Request::builder()
+                .method("POST")
+                .uri("https://example.com/items?id=1")
+                .header("content-type", "application/json")
+                .header("content-length", "2"),
+        );
+        let head = String::from_utf8(http1_head(&parts).unwrap()).unwrap();
+        assert_eq!(head, "POST /items?id=1 HTTP/1.1\r\nHost: example.com\r\ncontent-type: application/json\r\n");
+    }
+
+    #[test]
+    fn keeps_the_host_header() {
+        let parts = parts(http::Request::builder().uri("https://example.com/").header("host", "other.com"));
+        let head = String::from_utf8(http1_head(&parts).unwrap()).unwrap();
+        assert_eq!(head, "GET / HTTP/1.1\r\nhost: other.com\r\n");
+    }
+
+    #[test]
+    fn refuses_a_long_target() {
+        let target = format!("/{}", "a".repeat(MAX_URI_LENGTH - 1));
+        assert_eq!(code(http1_head(&parts(http::Request::builder().uri(target.as_str())))), 200);
+        let target = format!("/{}", "a".repeat(MAX_URI_LENGTH));
+        assert_eq!(code(http1_head(&parts(http::Request::builder().uri(target.as_str())))), 414);
+    }
+
+    #[test]
+    fn refuses_too_many_headers() {
+        //the host and the content length count too
+        let mut request = http::Request::builder().uri("https://example.com/");
+        for index in 0..MAX_HEADERS - 2 {
+            request = request.header(format!("x-header-{}", index), "1");
+        }
+        assert_eq!(code(http1_head(&parts(request))), 200);
+        let mut request = http::Request::builder().uri("https://example.com/");
+        for index in 0..MAX_HEADERS - 1 {
+            request = request.header(format!("x-header-{}", index), "1");
+        }
+        assert_eq!(code(http1_head(&parts(request))), 431);
+    }
+
+    #[test]
+    fn refuses_a_big_head() {
+        let request = http::Request::builder().uri("/").header("cookie", "a".repeat(MAX_HEAD_SIZE));
+        assert_eq!(code(http1_head(&parts(request))), 431);
+        let request = http::Request::builder().uri("/").header("cookie", "a".repeat(MAX_HEAD_SIZE / 2));
+        assert_eq!(code(http1_head(&parts(request))), 200);
+    }
+}
@@ -0,0 +1,18 @@
+pub mod connections;
+pub mod http1;
+pub mod http2;
+pub mod pool;
+pub mod reply;
+pub mod rewind;
+#[allow(clippy::module_inception)]
+pub mod server;
+pub mod websocket;
+pub mod write_timeout;
+
+pub use connections::*;
+pub use pool::*;
+pub use reply::*;
+pub use rewind::*;
+pub use server::*;
+pub use websocket::*;
+pub use write_timeout::*;
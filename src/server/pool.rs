@@ -0,0 +1,180 @@
+extern crate pyo3;
+
+use crate::app::App;
+use crate::config::Config;
+use crate::interpreter;
+use crate::server::{Job, Reply, WorkerMessage};
+use crate::{check_routes, get_routes};
+use pyo3::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+//the jobs waiting for a worker, the next idle worker takes the next one
+#[derive(Clone)]
+pub struct JobQueue {
+    receiver: Arc<Mutex<Receiver<WorkerMessage>>>,
+    //the workers busy with a websocket for as long as it is open
+    sessions: Arc<AtomicUsize>,
+}
+
+impl JobQueue {
+    pub fn new(receiver: Receiver<WorkerMessage>) -> Self {
+        Self {
+            receiver: Arc::new(Mutex::new(receiver)),
+            sessions: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    //waits for the next job, None when the worker has to stop. the workers of a reloaded pool
+    //stop once they took every job left in the queue
+    pub fn next(&self) -> Option<Job> {
+        self.receiver.lock().unwrap().recv().ok().flatten()
+    }
+
+    //counts the worker as taken until the session is dropped
+    pub fn session(&self) -> Session {
+        self.sessions.fetch_add(1, Ordering::SeqCst);
+        Session {
+            sessions: self.sessions.clone(),
+        }
+    }
+
+    fn sessions(&self) -> usize {
+        self.sessions.load(Ordering::SeqCst)
+    }
+}
+
+pub struct Session {
+    sessions: Arc<AtomicUsize>,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.sessions.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//the worker threads running the handlers, the idle ones take the requests in turn
+pub struct WorkerPool {
+    //the workers run clones of it, the server checks the requests with it before queueing them
+    app: Arc<App>,
+    config: Config,
+    sender: SyncSender<WorkerMessage>,
+    queue: JobQueue,
+    workers: usize,
+    request_counter: usize,
+    start_time: Instant,
+}
+
+impl WorkerPool {
+    pub fn start(app: App, config: Config) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(config.queue_size);
+        let mut pool = Self {
+            app: Arc::new(app),
+            sender,
+            queue: JobQueue::new(receiver),
+            workers: 0,
+            request_counter: 0,
+            start_time: Instant::now(),
+            config,
+        };
+        for _ in 0..pool.config.workers.max(1) {
+            pool.add_worker();
+        }
+        pool
+    }
+
+    pub fn app(&self) -> &App {
+        &self.app
+    }
+
+    //queues the job for the next idle worker, its reply is given back when the queue is full
+    pub fn dispatch(&mut self, job: Job) -> Result<(), Reply> {
+        //every worker is holding a websocket, one more takes the requests
+        if self.queue.sessions() >= self.workers {
+            self.add_worker();
+        }
+        let queued = match self.sender.try_send(Some(job)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(message)) | Err(TrySendError::Disconnected(message)) => {
+                Err(message.expect("only jobs are dispatched").reply)
+            }
+        };
+        //the refused requests count too, they are the traffic the workers could not keep up with
+        self.request_counter += 1;
+        self.scale();
+        queued
+    }
+
+    //adds or removes workers based on the traffic of the application
+    fn scale(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.start_time).as_secs() <= 60 {
+            return;
+        }
+        let ratio = self.request_counter as f64 / 60.0 / self.workers as f64;
+        if ratio > 5.0 {
+            //add more workers
+            self.add_worker();
+        } else if ratio < 0.2 && self.workers > 1 {
+            //remove some workers
+            self.remove_worker();
+        }
+        self.start_time = now;
+        self.request_counter = 0;
+    }
+
+    //the app with the routes and the code of the project discovered again, for `reload`. it
+    //takes a while, the pool keeps serving meanwhile
+    pub fn load_app(config: &Config) -> App {
+        println!("Changes detected, reloading the project");
+        let project_path = config.project_path.as_str();
+        //the main interpreter keeps the modules imported, forget them so the new workers import them again
+        {
+            let gil = Python::acquire_gil();
+            if let Err(traceback) = interpreter::unload_project_modules(gil.python(), project_path) {
+                traceback.print(gil.python());
+            }
+        }
+
+        let mut app = App::from_config(config.clone());
+        let routes = get_routes(project_path);
+        app.register_routes(routes.found);
+        //in development the broken routes are only reported and skipped, the server keeps running
+        check_routes(&app, &routes.problems);
+        app
+    }
+
+    //stops every worker and starts new ones running the app
+    pub fn reload(&mut self, app: App) {
+        let n_workers = self.workers.max(1);
+        //the old workers finish the jobs already queued, the new ones get a queue of their own
+        let (sender, receiver) = mpsc::sync_channel(self.config.queue_size);
+        self.sender = sender;
+        self.queue = JobQueue::new(receiver);
+        self.workers = 0;
+        self.app = Arc::new(app);
+        for _ in 0..n_workers {
+            self.add_worker();
+        }
+    }
+
+    fn add_worker(&mut self) {
+        let mut worker = App::clone(&self.app);
+        let queue = self.queue.clone();
+        thread::spawn(move || {
+            worker.start(queue);
+        });
+        self.workers += 1;
+    }
+
+    //the first idle worker stops, the workers are kept when the queue is full
+    fn remove_worker(&mut self) {
+        if self.sender.try_send(None).is_ok() {
+            self.workers -= 1;
+        }
+    }
+}
@@ -0,0 +1,168 @@
+extern crate tokio;
+
+use crate::ratelimit::RateLimitStatus;
+use crate::request::Request;
+use crate::response::{Body, Response, ResponseHead, KEEP_ALIVE_COMMENT};
+use crate::server::WebSocketBridge;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+//the biggest piece of a file sent at once
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+//the pieces a worker can get ahead of the client before waiting for it
+const REPLY_BUFFER: usize = 16;
+
+//a request for a worker, with the way back to its connection
+pub struct Job {
+    //parsed by the server, the client is already resolved
+    pub request: Request,
+    pub reply: Reply,
+    //when the request started waiting for a worker
+    pub queued_at: Instant,
+    //the token the request took, its headers go on the response
+    pub rate_limit: Option<RateLimitStatus>,
+}
+
+//what the workers get, None stops them
+pub type WorkerMessage = Option<Job>;
+
+//what a connection gets from the worker, in order: the head, the pieces of the body and the end.
+//the worker can stop before the end when the body fails
+pub enum ReplyEvent {
+    //`body` is false when nothing follows the head, like for HEAD requests
+    Head { head: ResponseHead, body: bool },
+    Chunk(Vec<u8>),
+    End,
+    //the connection switches to the websocket protocol after the head, nothing else follows
+    Upgrade { head: ResponseHead, bridge: WebSocketBridge },
+}
+
+//sends the response of a worker to its connection. the worker produces the body, so the
+//streams of the handlers are only touched by the thread owning their interpreter
+pub struct Reply {
+    sender: mpsc::Sender<ReplyEvent>,
+    //set by the first reply sending something, the others are dropped
+    answered: Arc<AtomicBool>,
+}
+
+impl Reply {
+    pub fn channel() -> (Self, mpsc::Receiver<ReplyEvent>) {
+        let (sender, receiver) = mpsc::channel(REPLY_BUFFER);
+        let reply = Self {
+            sender,
+            answered: Arc::new(AtomicBool::new(false)),
+        };
+        (reply, receiver)
+    }
+
+    //another way to answer the same request, like when the handler takes too long. the
+    //client gets whichever is sent first
+    pub fn fallback(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            answered: self.answered.clone(),
+        }
+    }
+
+    fn claim(&self) -> bool {
+        !self.answered.swap(true, Ordering::SeqCst)
+    }
+
+    //waits for the connection to take every piece, the body stops being produced as soon as
+    //the client is gone
+    pub fn send(self, response: Response) {
+        if !self.claim() {
+            return;
+        }
+        let (head, body) = response.into_parts();
+        let has_body = body.is_some();
+        if self.sender.blocking_send(ReplyEvent::Head { head, body: has_body }).is_err() {
+            return;
+        }
+        let complete = match body {
+            Some(body) => self.send_body(body).is_ok(),
+            None => true,
+        };
+        if complete {
+            let _ = self.sender.blocking_send(ReplyEvent::End);
+        }
+    }
+
+    //answers without waiting, for the responses made outside the workers. the body has to be
+    //in memory and small enough for the buffer
+    pub fn send_now(self, response: Response) {
+        if !self.claim() {
+            return;
+        }
+        let (head, body) = response.into_parts();
+        let _ = self.sender.try_send(ReplyEvent::Head { head, body: body.is_some() });
+        if let Some(Body::Bytes(bytes)) = body {
+            let _ = self.sender.try_send(ReplyEvent::Chunk(bytes));
+        }
+        let _ = self.sender.try_send(ReplyEvent::End);
+    }
+
+    //hands the connection over to the websocket of the handler, false when the client is gone
+    pub fn upgrade(self, response: Response, bridge: WebSocketBridge) -> bool {
+        if !self.claim() {
+            return false;
+        }
+        let (head, _) = response.into_parts();
+        self.sender.blocking_send(ReplyEvent::Upgrade { head, bridge }).is_ok()
+    }
+
+    fn send_body(&self, body: Body) -> io::Result<()> {
+        match body {
+            Body::Bytes(bytes) => self.send_chunk(bytes),
+            Body::File { file, offset, length } => {
+                let mut file: &File = &file;
+                file.seek(SeekFrom::Start(offset))?;
+                let mut remaining = length;
+                while remaining > 0 {
+                    let mut chunk = vec![0; FILE_CHUNK_SIZE.min(remaining as usize)];
+                    let bytes_read = file.read(&mut chunk)?;
+                    //the file got shorter than the length we announced
+                    if bytes_read == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    chunk.truncate(bytes_read);
+                    remaining -= bytes_read as u64;
+                    self.send_chunk(chunk)?;
+                }
+                Ok(())
+            }
+            Body::Stream(mut source) => {
+                while let Some(chunk) = source.next_chunk()? {
+                    self.send_chunk(chunk)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn send_chunk(&self, chunk: Vec<u8>) -> io::Result<()> {
+        //an empty chunk would end a chunked body
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        self.sender
+            .blocking_send(ReplyEvent::Chunk(chunk))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+//the next piece of the body. the silent event streams get a keep-alive comment every `keep_alive`
+pub async fn next_chunk(receiver: &mut mpsc::Receiver<ReplyEvent>, keep_alive: Option<Duration>) -> Option<ReplyEvent> {
+    let keep_alive = match keep_alive {
+        Some(keep_alive) => keep_alive,
+        None => return receiver.recv().await,
+    };
+    match tokio::time::timeout(keep_alive, receiver.recv()).await {
+        Ok(event) => event,
+        Err(_) => Some(ReplyEvent::Chunk(KEEP_ALIVE_COMMENT.to_vec())),
+    }
+}
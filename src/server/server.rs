@@ -0,0 +1,198 @@
+extern crate tokio;
+
+use crate::config::Config;
+use crate::request::{BodyLimits, RawRequest, ReadTimeouts, Request};
+use crate::response::{Body, Response, ResponseHead};
+use crate::server::{
+    http1, http2, ConnectionLimits, ConnectionSlot, Job, Reply, ReplyEvent, Rewind, WebSocketLimits, WorkerPool, WriteTimeout,
+};
+use crate::tls::{self, Tls};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use std::time::Instant;
+
+//the first bytes of every http/2 connection
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+//how long to wait before accepting again when it failed, like when we ran out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+//what the requests are told about the connection they came from
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    pub peer_addr: Option<SocketAddr>,
+    pub secure: bool,
+    //the certificate the client authenticated with, in der
+    pub client_certificate: Option<Vec<u8>>,
+}
+
+impl ConnectionInfo {
+    pub fn apply(&self, request: &mut RawRequest) {
+        request.peer_addr = self.peer_addr;
+        request.secure = self.secure;
+        request.client_certificate = self.client_certificate.clone();
+    }
+}
+
+//accepts the connections and reads their requests, the handlers run in the worker pool
+pub struct Server {
+    pool: Arc<Mutex<WorkerPool>>,
+    tls: Option<Arc<Tls>>,
+    pub limits: BodyLimits,
+    pub websocket_limits: WebSocketLimits,
+    pub sse_keep_alive: Duration,
+    pub read_timeouts: ReadTimeouts,
+    //how long a connection is kept without requests
+    pub idle_timeout: Duration,
+    write_timeout: Duration,
+    connection_limits: ConnectionLimits,
+    retry_after: u64,
+    //accept the h2c connections, the tls ones negotiate it with alpn
+    http2: bool,
+}
+
+impl Server {
+    pub fn new(pool: Arc<Mutex<WorkerPool>>, tls: Option<Arc<Tls>>, config: &Config) -> Self {
+        Self {
+            pool,
+            tls,
+            limits: BodyLimits::from_config(config),
+            websocket_limits: WebSocketLimits::from_config(config),
+            sse_keep_alive: Duration::from_secs(config.sse_keep_alive_interval.max(1)),
+            read_timeouts: ReadTimeouts::from_config(config),
+            idle_timeout: Duration::from_secs(config.idle_timeout.max(1)),
+            write_timeout: Duration::from_secs(config.write_timeout.max(1)),
+            connection_limits: ConnectionLimits::from_config(config),
+            retry_after: config.retry_after,
+            http2: config.http2,
+        }
+    }
+
+    pub async fn run(self: Arc<Self>, listener: std::net::TcpListener) {
+        listener.set_nonblocking(true).unwrap();
+        let listener = TcpListener::from_std(listener).unwrap();
+        if let Some(tls) = &self.tls {
+            tls::reload_on_hangup(tls.clone());
+        }
+        loop {
+            //at the limit the connections are not accepted, so they wait in the backlog
+            let room = self.connection_limits.room().await;
+            let (socket, peer_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    println!("Could not accept a connection: {}", err);
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            //a client with too many connections gets the new ones closed right away
+            let slot = match self.connection_limits.admit(room, peer_addr.ip()) {
+                Some(slot) => slot,
+                None => continue,
+            };
+            let server = self.clone();
+            tokio::spawn(async move { server.serve_connection(socket, peer_addr, slot).await });
+        }
+    }
+
+    //gives the request to a worker, its response comes back through the receiver. when too
+    //many requests are waiting, or the client is over its rate limit, it is refused right away
+    pub fn dispatch(&self, request: RawRequest) -> mpsc::Receiver<ReplyEvent> {
+        let (reply, receiver) = Reply::channel();
+        //the request is only parsed here, the worker gets it ready
+        let mut request = match Request::parse(request) {
+            Ok(request) => request,
+            Err(err) => {
+                reply.send_now(err.response());
+                return receiver;
+            }
+        };
+        //a reload can not happen in between, the request is checked by the app of the workers it goes to
+        let mut pool = self.pool.lock().unwrap();
+        pool.app().resolve_client(&mut request);
+        let rate_limit = pool.app().check_rate_limit(&request);
+        if let Some(status) = rate_limit.as_ref().filter(|status| !status.allowed()) {
+            reply.send_now(status.response());
+            return receiver;
+        }
+        let job = Job {
+            request,
+            reply,
+            queued_at: Instant::now(),
+            rate_limit,
+        };
+        if let Err(reply) = pool.dispatch(job) {
+            reply.send_now(overloaded(self.retry_after));
+        }
+        receiver
+    }
+
+    //the connection keeps its slot until it is closed
+    async fn serve_connection(self: Arc<Self>, socket: TcpStream, peer_addr: SocketAddr, _slot: ConnectionSlot) {
+        let _ = socket.set_nodelay(true);
+        let socket = WriteTimeout::new(socket, self.write_timeout);
+        let mut connection = ConnectionInfo {
+            peer_addr: Some(peer_addr),
+            ..Default::default()
+        };
+        let tls = match &self.tls {
+            Some(tls) => tls,
+            None => return self.serve(socket, connection, false).await,
+        };
+        //the handshake failed, the client already got the tls alert
+        let stream = match tls.accept(socket).await {
+            Ok(stream) => stream,
+            Err(_) => return,
+        };
+        let (_, session) = stream.get_ref();
+        connection.secure = true;
+        connection.client_certificate = session.peer_certificates().and_then(|certs| certs.first()).map(|cert| cert.to_vec());
+        let negotiated_http2 = session.alpn_protocol() == Some(b"h2");
+        self.serve(stream, connection, negotiated_http2).await
+    }
+
+    async fn serve<S>(self: Arc<Self>, mut stream: S, connection: ConnectionInfo, negotiated_http2: bool)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        if negotiated_http2 {
+            return http2::serve(self, stream, connection).await;
+        }
+        //the clients knowing we speak http/2 start with its preface, without negotiating it
+        let mut buffer = Vec::new();
+        let preface_deadline = tokio::time::Instant::now() + self.read_timeouts.header;
+        while self.http2 && buffer.len() < HTTP2_PREFACE.len() && HTTP2_PREFACE.starts_with(&buffer) {
+            match tokio::time::timeout_at(preface_deadline, stream.read_buf(&mut buffer)).await {
+                Ok(Ok(0)) | Ok(Err(_)) | Err(_) => return,
+                Ok(Ok(_)) => (),
+            }
+        }
+        if self.http2 && buffer.starts_with(HTTP2_PREFACE) {
+            return http2::serve(self, Rewind::new(buffer, stream), connection).await;
+        }
+        http1::serve(&self, stream, buffer, connection).await
+    }
+}
+
+//the responses made by the connections themselves, their body is always in memory
+pub fn simple_response(response: Response) -> (ResponseHead, Vec<u8>) {
+    let (head, body) = response.into_parts();
+    let body = match body {
+        Some(Body::Bytes(bytes)) => bytes,
+        _ => Vec::new(),
+    };
+    (head, body)
+}
+
+//the answer when no worker took the request
+pub fn unavailable() -> (ResponseHead, Vec<u8>) {
+    simple_response(Response::new(503, "text/plain; charset=utf-8", "Service Unavailable"))
+}
+
+//the answer when the workers are too busy to take the request
+pub fn overloaded(retry_after: u64) -> Response {
+    Response::new(503, "text/plain; charset=utf-8", "Service Unavailable").with_header("Retry-After", &retry_after.to_string())
+}
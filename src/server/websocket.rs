@@ -0,0 +1,211 @@
+extern crate futures_util;
+extern crate tokio;
+extern crate tokio_tungstenite;
+
+use crate::config::Config;
+use futures_util::{SinkExt, StreamExt};
+use std::sync::mpsc::{self as std_mpsc, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role, WebSocketConfig};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::WebSocketStream;
+
+//the messages the connection reads ahead of the handler, before it stops reading
+const INCOMING_BUFFER: usize = 16;
+//the messages the handler can send ahead of the client, before its sends wait
+const OUTGOING_BUFFER: usize = 16;
+//how long the client has to answer our close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+//the messages between the handler and the client. the close carries its code and reason
+#[derive(Debug)]
+pub enum WebSocketMessage {
+    Text(String),
+    Binary(Vec<u8>),
+    Close(u16, String),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WebSocketLimits {
+    pub max_message_size: usize,
+    pub ping_interval: Duration,
+}
+
+impl WebSocketLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_message_size: config.websocket_max_message_size,
+            ping_interval: Duration::from_secs(config.websocket_ping_interval.max(1)),
+        }
+    }
+}
+
+//the end of the websocket owned by the worker, every call blocks the worker thread
+pub struct WebSocketPeer {
+    incoming: Mutex<std_mpsc::Receiver<WebSocketMessage>>,
+    //a message taken by the handler lets the connection read another one
+    credits: Arc<Semaphore>,
+    outgoing: mpsc::Sender<WebSocketMessage>,
+}
+
+//the end of the websocket owned by the connection
+pub struct WebSocketBridge {
+    incoming: std_mpsc::Sender<WebSocketMessage>,
+    credits: Arc<Semaphore>,
+    outgoing: mpsc::Receiver<WebSocketMessage>,
+}
+
+pub fn websocket_channel() -> (WebSocketPeer, WebSocketBridge) {
+    let (incoming_sender, incoming_receiver) = std_mpsc::channel();
+    let (outgoing_sender, outgoing_receiver) = mpsc::channel(OUTGOING_BUFFER);
+    let credits = Arc::new(Semaphore::new(INCOMING_BUFFER));
+    let peer = WebSocketPeer {
+        incoming: Mutex::new(incoming_receiver),
+        credits: credits.clone(),
+        outgoing: outgoing_sender,
+    };
+    let bridge = WebSocketBridge {
+        incoming: incoming_sender,
+        credits,
+        outgoing: outgoing_receiver,
+    };
+    (peer, bridge)
+}
+
+impl WebSocketPeer {
+    //waits for the next message of the client. disconnected means the connection is gone
+    pub fn receive(&self, timeout: Option<Duration>) -> Result<WebSocketMessage, RecvTimeoutError> {
+        let incoming = self.incoming.lock().unwrap();
+        let message = match timeout {
+            Some(timeout) => incoming.recv_timeout(timeout)?,
+            None => incoming.recv().map_err(|_| RecvTimeoutError::Disconnected)?,
+        };
+        self.credits.add_permits(1);
+        Ok(message)
+    }
+
+    //waits while the client is behind, false when the connection is gone
+    pub fn send(&self, message: WebSocketMessage) -> bool {
+        self.outgoing.blocking_send(message).is_ok()
+    }
+}
+
+//speaks the websocket protocol on the upgraded connection, passing the messages between the
+//client and the handler. the pings, the pongs and the closing handshake never reach python.
+//`buffer` has the bytes the client sent right after its handshake
+pub async fn serve<S>(stream: S, buffer: Vec<u8>, bridge: WebSocketBridge, limits: WebSocketLimits)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(limits.max_message_size))
+        .max_frame_size(Some(limits.max_message_size));
+    let mut socket = WebSocketStream::from_partially_read(stream, buffer, Role::Server, Some(config)).await;
+    let WebSocketBridge {
+        incoming,
+        credits,
+        mut outgoing,
+    } = bridge;
+
+    let mut ping = tokio::time::interval(limits.ping_interval);
+    ping.tick().await;
+    let mut awaiting_pong = false;
+    //the next message read has room in the handler queue
+    let mut credit = false;
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            permit = credits.acquire(), if !credit => match permit {
+                Ok(permit) => {
+                    permit.forget();
+                    credit = true;
+                }
+                Err(_) => break,
+            },
+            message = socket.next(), if credit => {
+                let message = match message {
+                    Some(Ok(message)) => message,
+                    Some(Err(Error::Capacity(err))) => {
+                        let reason = err.to_string();
+                        let _ = incoming.send(WebSocketMessage::Close(u16::from(CloseCode::Size), reason.clone()));
+                        let _ = socket.close(Some(close_frame(CloseCode::Size, reason))).await;
+                        closing = true;
+                        break;
+                    }
+                    Some(Err(_)) | None => break,
+                };
+                //any frame tells the client is still there
+                awaiting_pong = false;
+                match message {
+                    Message::Text(text) => {
+                        credit = false;
+                        let _ = incoming.send(WebSocketMessage::Text(text.to_string()));
+                    }
+                    Message::Binary(data) => {
+                        credit = false;
+                        let _ = incoming.send(WebSocketMessage::Binary(data.to_vec()));
+                    }
+                    //the close is answered by tungstenite, the stream ends right after
+                    Message::Close(frame) => {
+                        let (code, reason) = frame
+                            .map(|frame| (u16::from(frame.code), frame.reason.to_string()))
+                            .unwrap_or((u16::from(CloseCode::Status), String::new()));
+                        let _ = incoming.send(WebSocketMessage::Close(code, reason));
+                    }
+                    //the pings are answered by tungstenite
+                    Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => (),
+                }
+            },
+            message = outgoing.recv() => {
+                let message = match message {
+                    Some(WebSocketMessage::Text(text)) => Message::Text(text.into()),
+                    Some(WebSocketMessage::Binary(data)) => Message::Binary(data.into()),
+                    Some(WebSocketMessage::Close(code, reason)) => {
+                        let _ = socket.close(Some(close_frame(CloseCode::from(code), reason))).await;
+                        closing = true;
+                        break;
+                    }
+                    //the handler is done without closing
+                    None => {
+                        let _ = socket.close(Some(close_frame(CloseCode::Normal, String::new()))).await;
+                        closing = true;
+                        break;
+                    }
+                };
+                if socket.send(message).await.is_err() {
+                    break;
+                }
+            },
+            _ = ping.tick() => {
+                //the pongs of a client are not read while the handler is behind
+                if awaiting_pong && credit {
+                    break;
+                }
+                awaiting_pong = true;
+                if socket.send(Message::Ping(Default::default())).await.is_err() {
+                    break;
+                }
+            },
+        }
+    }
+    //the handler sees the connection closed once the incoming messages are dropped
+    drop(incoming);
+    if closing {
+        //waits for the close of the client, the messages it sent meanwhile are dropped
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+            while let Some(Ok(_)) = socket.next().await {}
+        })
+        .await;
+    }
+}
+
+fn close_frame(code: CloseCode, reason: String) -> CloseFrame {
+    CloseFrame {
+        code,
+        reason: reason.into(),
+    }
+}
@@ -0,0 +1,385 @@
+extern crate rustls;
+extern crate rustls_pki_types;
+extern crate tokio;
+extern crate tokio_rustls;
+
+use crate::config::{ClientAuth, TlsConfig};
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+//the clients taking longer to finish the handshake are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+//terminates the tls connections, with the certificates of the config
+pub struct Tls {
+    config: TlsConfig,
+    project_path: String,
+    //the protocols offered with alpn, by preference
+    alpn_protocols: Vec<Vec<u8>>,
+    //replaced on reload, the connections already accepted keep the one they started with
+    server_config: RwLock<Arc<ServerConfig>>,
+}
+
+impl Tls {
+    pub fn load(config: &TlsConfig, project_path: &str, http2: bool) -> Result<Self, String> {
+        let alpn_protocols = match http2 {
+            true => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+            false => vec![b"http/1.1".to_vec()],
+        };
+        let server_config = build_server_config(config, project_path, &alpn_protocols)?;
+        Ok(Self {
+            config: config.clone(),
+            project_path: project_path.to_string(),
+            alpn_protocols,
+            server_config: RwLock::new(server_config),
+        })
+    }
+
+    //reads the certificates and keys again, the current ones are kept when they can not be loaded
+    pub fn reload(&self) -> Result<(), String> {
+        let server_config = build_server_config(&self.config, &self.project_path, &self.alpn_protocols)?;
+        *self.server_config.write().unwrap() = server_config;
+        Ok(())
+    }
+
+    //completes the handshake with the client
+    pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(&self, socket: S) -> io::Result<TlsStream<S>> {
+        let acceptor = TlsAcceptor::from(self.server_config.read().unwrap().clone());
+        match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+            Ok(stream) => stream,
+            Err(_) => Err(io::ErrorKind::TimedOut.into()),
+        }
+    }
+}
+
+//reloads the certificates every time the process gets a SIGHUP, like after they were renewed.
+//it has to be called from the runtime
+pub fn reload_on_hangup(tls: Arc<Tls>) {
+    //the handler is installed before returning, a SIGHUP would stop the server otherwise
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            println!("The tls certificates can not be reloaded on SIGHUP: {}", err);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            match tls.reload() {
+                Ok(_) => println!("Reloaded the tls certificates"),
+                Err(err) => println!("Could not reload the tls certificates, keeping the current ones: {}", err),
+            }
+        }
+    });
+}
+
+fn build_server_config(
+    config: &TlsConfig,
+    project_path: &str,
+    alpn_protocols: &[Vec<u8>],
+) -> Result<Arc<ServerConfig>, String> {
+    let provider = Arc::new(ring::default_provider());
+    let resolver = CertificateResolver::load(config, project_path, &provider)?;
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?;
+    let builder = match &config.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(&Path::new(project_path).join(client_ca))? {
+                roots.add(cert).map_err(|err| format!("{}: {}", client_ca, err))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match config.client_auth {
+                ClientAuth::Required => verifier,
+                ClientAuth::Optional => verifier.allow_unauthenticated(),
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(|err| err.to_string())?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_cert_resolver(Arc::new(resolver));
+    server_config.alpn_protocols = alpn_protocols.to_vec();
+    Ok(Arc::new(server_config))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificate found", path.display()));
+    }
+    Ok(certs)
+}
+
+//picks the certificate by the server name the client asked for with sni
+#[derive(Debug)]
+struct CertificateResolver {
+    //the names are lowercase, wildcards keep their `*.`
+    names: Vec<(String, Arc<CertifiedKey>)>,
+    default: Arc<CertifiedKey>,
+}
+
+impl CertificateResolver {
+    fn load(config: &TlsConfig, project_path: &str, provider: &CryptoProvider) -> Result<Self, String> {
+        let mut names = Vec::new();
+        let mut default = None;
+        for certificate in &config.certificates {
+            let certs = load_certs(&Path::new(project_path).join(&certificate.cert))?;
+            let key_path = Path::new(project_path).join(&certificate.key);
+            let key = PrivateKeyDer::from_pem_file(&key_path)
+                .map_err(|err| format!("{}: {}", key_path.display(), err))?;
+            let key = provider
+                .key_provider
+                .load_private_key(key)
+                .map_err(|err| format!("{}: {}", key_path.display(), err))?;
+            let certified = Arc::new(CertifiedKey::new(certs, key));
+            certified
+                .keys_match()
+                .map_err(|err| format!("{} does not match {}: {}", key_path.display(), certificate.cert, err))?;
+            for name in &certificate.server_names {
+                names.push((name.to_ascii_lowercase(), certified.clone()));
+            }
+            default.get_or_insert(certified);
+        }
+        match default {
+            Some(default) => Ok(Self { names, default }),
+            None => Err("at least one certificate is needed".to_string()),
+        }
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let server_name = match client_hello.server_name() {
+            Some(server_name) => server_name.to_ascii_lowercase(),
+            None => return Some(self.default.clone()),
+        };
+        //exact names win over the wildcards, whatever their order
+        let exact = self.names.iter().find(|(name, _)| *name == server_name);
+        let wildcard = || {
+            let (_, parent) = server_name.split_once('.')?;
+            self.names
+                .iter()
+                .find(|(name, _)| name.strip_prefix("*.") == Some(parent))
+        };
+        let certified = exact.or_else(wildcard).map(|(_, certified)| certified);
+        Some(certified.unwrap_or(&self.default).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TlsCertificate;
+    use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+    use rustls::ClientConfig;
+    use rustls_pki_types::ServerName;
+    use std::fs;
+    use tokio::io::{duplex, DuplexStream};
+    use tokio_rustls::{client, TlsConnector};
+
+    //signs the certificates of the servers and the clients
+    struct Authority {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    impl Authority {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let key = KeyPair::generate().unwrap();
+            let cert = params.self_signed(&key).unwrap();
+            Self { cert, key }
+        }
+
+        //the certificate and the key, as pem
+        fn issue(&self, names: &[&str], purpose: ExtendedKeyUsagePurpose) -> (String, String) {
+            let mut params = CertificateParams::new(names.iter().map(|name| name.to_string()).collect::<Vec<_>>()).unwrap();
+            params.extended_key_usages = vec![purpose];
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+            (cert.pem(), key.serialize_pem())
+        }
+
+        //issues a server certificate into the project, as `<file>.pem` and `<file>.key`
+        fn server_certificate(&self, project: &Path, file: &str, names: &[&str]) -> TlsCertificate {
+            let (cert, key) = self.issue(names, ExtendedKeyUsagePurpose::ServerAuth);
+            fs::write(project.join(format!("{}.pem", file)), cert).unwrap();
+            fs::write(project.join(format!("{}.key", file)), key).unwrap();
+            TlsCertificate {
+                cert: format!("{}.pem", file),
+                key: format!("{}.key", file),
+                server_names: names.iter().map(|name| name.to_string()).collect(),
+            }
+        }
+
+        fn client(&self, identity: Option<&(String, String)>, alpn: &[&[u8]]) -> TlsConnector {
+            let mut roots = RootCertStore::empty();
+            roots.add(self.cert.der().clone()).unwrap();
+            let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+            let mut config = match identity {
+                Some((cert, key)) => {
+                    let certs = vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()];
+                    let key = PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap();
+                    builder.with_client_auth_cert(certs, key).unwrap()
+                }
+                None => builder.with_no_client_auth(),
+            };
+            config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+            TlsConnector::from(Arc::new(config))
+        }
+    }
+
+    type Handshake = (io::Result<TlsStream<DuplexStream>>, io::Result<client::TlsStream<DuplexStream>>);
+
+    //the server and the client side of a handshake, over an in memory connection
+    async fn handshake(tls: &Tls, connector: &TlsConnector, server_name: &str) -> Handshake {
+        let (client_side, server_side) = duplex(64 * 1024);
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        tokio::join!(tls.accept(server_side), connector.connect(server_name, client_side))
+    }
+
+    //the certificate the server sent to the client
+    fn presented(stream: &client::TlsStream<DuplexStream>) -> CertificateDer<'static> {
+        stream.get_ref().1.peer_certificates().unwrap()[0].clone().into_owned()
+    }
+
+    fn certificate_of(project: &Path, file: &str) -> CertificateDer<'static> {
+        load_certs(&project.join(file)).unwrap().remove(0)
+    }
+
+    fn tls_config(certificates: Vec<TlsCertificate>) -> TlsConfig {
+        TlsConfig {
+            certificates,
+            client_ca: None,
+            client_auth: ClientAuth::Required,
+        }
+    }
+
+    #[tokio::test]
+    async fn completes_the_handshake() {
+        let project = tempfile::tempdir().unwrap();
+        let authority = Authority::new();
+        let config = tls_config(vec![authority.server_certificate(project.path(), "site", &["example.test"])]);
+        let tls = Tls::load(&config, project.path().to_str().unwrap(), false).unwrap();
+
+        let (server, client) = handshake(&tls, &authority.client(None, &[]), "example.test").await;
+        assert!(server.is_ok());
+        assert_eq!(presented(&client.unwrap()), certificate_of(project.path(), "site.pem"));
+    }
+
+    #[tokio::test]
+    async fn picks_the_certificate_by_server_name() {
+        let project = tempfile::tempdir().unwrap();
+        let authority = Authority::new();
+        let config = tls_config(vec![
+            authority.server_certificate(project.path(), "first", &["first.test"]),
+            authority.server_certificate(project.path(), "second", &["second.test"]),
+            authority.server_certificate(project.path(), "wildcard", &["*.wild.test"]),
+        ]);
+        let tls = Tls::load(&config, project.path().to_str().unwrap(), false).unwrap();
+        let connector = authority.client(None, &[]);
+
+        for (server_name, file) in [("first.test", "first.pem"), ("second.test", "second.pem"), ("any.wild.test", "wildcard.pem")] {
+            let (_, client) = handshake(&tls, &connector, server_name).await;
+            assert_eq!(presented(&client.unwrap()), certificate_of(project.path(), file), "{}", server_name);
+        }
+    }
+
+    #[tokio::test]
+    async fn negotiates_http2_only_when_enabled() {
+        let project = tempfile::tempdir().unwrap();
+        let authority = Authority::new();
+        let config = tls_config(vec![authority.server_certificate(project.path(), "site", &["example.test"])]);
+        let connector = authority.client(None, &[b"h2", b"http/1.1"]);
+
+        for (http2, protocol) in [(true, b"h2".as_slice()), (false, b"http/1.1".as_slice())] {
+            let tls = Tls::load(&config, project.path().to_str().unwrap(), http2).unwrap();
+            let (server, _) = handshake(&tls, &connector, "example.test").await;
+            assert_eq!(server.unwrap().get_ref().1.alpn_protocol(), Some(protocol));
+        }
+    }
+
+    #[tokio::test]
+    async fn requires_or_accepts_client_certificates() {
+        let project = tempfile::tempdir().unwrap();
+        let authority = Authority::new();
+        fs::write(project.path().join("clients.pem"), authority.cert.pem()).unwrap();
+        let mut config = tls_config(vec![authority.server_certificate(project.path(), "site", &["example.test"])]);
+        config.client_ca = Some("clients.pem".to_string());
+        let identity = authority.issue(&[], ExtendedKeyUsagePurpose::ClientAuth);
+        let with_identity = authority.client(Some(&identity), &[]);
+        let anonymous = authority.client(None, &[]);
+        //signed by another authority
+        let stranger = Authority::new().issue(&[], ExtendedKeyUsagePurpose::ClientAuth);
+        let with_stranger = authority.client(Some(&stranger), &[]);
+
+        let tls = Tls::load(&config, project.path().to_str().unwrap(), false).unwrap();
+        let (server, _) = handshake(&tls, &with_identity, "example.test").await;
+        assert!(server.unwrap().get_ref().1.peer_certificates().is_some());
+        assert!(handshake(&tls, &anonymous, "example.test").await.0.is_err());
+        assert!(handshake(&tls, &with_stranger, "example.test").await.0.is_err());
+
+        config.client_auth = ClientAuth::Optional;
+        let tls = Tls::load(&config, project.path().to_str().unwrap(), false).unwrap();
+        let (server, _) = handshake(&tls, &anonymous, "example.test").await;
+        assert!(server.unwrap().get_ref().1.peer_certificates().is_none());
+        let (server, _) = handshake(&tls, &with_identity, "example.test").await;
+        assert!(server.unwrap().get_ref().1.peer_certificates().is_some());
+        assert!(handshake(&tls, &with_stranger, "example.test").await.0.is_err());
+    }
+
+    #[tokio::test]
+    async fn reloads_the_certificates_on_hangup() {
+        let project = tempfile::tempdir().unwrap();
+        let authority = Authority::new();
+        let config = tls_config(vec![authority.server_certificate(project.path(), "site", &["example.test"])]);
+        let tls = Arc::new(Tls::load(&config, project.path().to_str().unwrap(), false).unwrap());
+        let connector = authority.client(None, &[]);
+        let before = certificate_of(project.path(), "site.pem");
+        reload_on_hangup(tls.clone());
+
+        //a broken certificate is not loaded, the current one is kept
+        fs::write(project.path().join("site.pem"), "not a certificate").unwrap();
+        assert!(tls.reload().is_err());
+        let (_, client) = handshake(&tls, &connector, "example.test").await;
+        assert_eq!(presented(&client.unwrap()), before);
+
+        //renewed
+        authority.server_certificate(project.path(), "site", &["example.test"]);
+        let after = certificate_of(project.path(), "site.pem");
+        assert_ne!(before, after);
+        unsafe {
+            libc::raise(libc::SIGHUP);
+        }
+        let mut current = before;
+        for _ in 0..50 {
+            let (_, client) = handshake(&tls, &connector, "example.test").await;
+            current = presented(&client.unwrap());
+            if current == after {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(current, after);
+    }
+}